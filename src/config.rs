@@ -0,0 +1,272 @@
+use crate::filter::{AgeBy, NamePattern};
+use crate::{units, Args};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// name of the profile that is applied underneath any selected profile
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// content of a TOML configuration file
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// sizes can be given as plain number of bytes or with units like "64K"
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Size {
+    fn bytes(&self) -> Result<u64, String> {
+        match self {
+            Size::Bytes(bytes) => Ok(*bytes),
+            Size::Text(text) => units::parse_size(text),
+        }
+    }
+}
+
+/// a named set of options, every option not given on the command line is taken from here
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    directories: Option<Vec<PathBuf>>,
+    include: Option<Vec<String>>,
+    exclude_files: Option<Vec<String>>,
+    exclude_dirs: Option<Vec<String>>,
+    min_size: Option<Size>,
+    max_size: Option<Size>,
+    peek_hash: Option<Size>,
+    min_age: Option<String>,
+    max_age: Option<String>,
+    age_by: Option<String>,
+    ignore_file: Option<String>,
+    match_path: Option<bool>,
+    ignore_case: Option<bool>,
+    csv_export: Option<PathBuf>,
+    scan_cache: Option<PathBuf>,
+    report_duplicates: Option<bool>,
+    timings: Option<bool>,
+    link_duplicates: Option<bool>,
+}
+
+// options set in other override those in profile
+macro_rules! merge_fields {
+    ($profile : expr, $other : expr, $($field : ident),*) => {
+        $(
+            if $other.$field.is_some() {
+                $profile.$field = $other.$field;
+            }
+        )*
+    };
+}
+
+impl Profile {
+    fn merge(&mut self, other: Profile) {
+        merge_fields!(
+            self,
+            other,
+            directories,
+            include,
+            exclude_files,
+            exclude_dirs,
+            min_size,
+            max_size,
+            peek_hash,
+            min_age,
+            max_age,
+            age_by,
+            ignore_file,
+            match_path,
+            ignore_case,
+            csv_export,
+            scan_cache,
+            report_duplicates,
+            timings,
+            link_duplicates
+        );
+    }
+
+    /// set all options of args that were not given on the command line
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        let patterns = |patterns: &Vec<String>| {
+            patterns
+                .iter()
+                .map(|p| NamePattern::parse(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        if let Some(ref directories) = self.directories {
+            if unset("directories") {
+                args.directories = directories.clone();
+            }
+        }
+        if let Some(ref include) = self.include {
+            if unset("include") {
+                args.include = patterns(include)?;
+            }
+        }
+        if let Some(ref exclude_files) = self.exclude_files {
+            if unset("exclude_files") {
+                args.exclude_files = patterns(exclude_files)?;
+            }
+        }
+        if let Some(ref exclude_dirs) = self.exclude_dirs {
+            if unset("exclude_dirs") {
+                args.exclude_dirs = patterns(exclude_dirs)?;
+            }
+        }
+        if let Some(ref min_size) = self.min_size {
+            if unset("min_size") {
+                args.min_size = min_size.bytes()?;
+            }
+        }
+        if let Some(ref max_size) = self.max_size {
+            if unset("max_size") {
+                args.max_size = max_size.bytes()?;
+            }
+        }
+        if let Some(ref peek_hash) = self.peek_hash {
+            if unset("peek_hash") {
+                args.peek_hash = peek_hash.bytes()?;
+            }
+        }
+        if let Some(ref min_age) = self.min_age {
+            if unset("min_age") {
+                args.min_age = Some(units::parse_duration(min_age)?);
+            }
+        }
+        if let Some(ref max_age) = self.max_age {
+            if unset("max_age") {
+                args.max_age = Some(units::parse_duration(max_age)?);
+            }
+        }
+        if let Some(ref age_by) = self.age_by {
+            if unset("age_by") {
+                args.age_by = AgeBy::from_str(age_by, true)?;
+            }
+        }
+        if let Some(ref ignore_file) = self.ignore_file {
+            if unset("ignore_file") {
+                args.ignore_file = ignore_file.clone();
+            }
+        }
+        if let Some(ref csv_export) = self.csv_export {
+            if unset("csv_export") {
+                args.csv_export = Some(csv_export.clone());
+            }
+        }
+        if let Some(ref scan_cache) = self.scan_cache {
+            if unset("scan_cache") {
+                args.scan_cache = Some(scan_cache.clone());
+            }
+        }
+        // flags given on the command line win, otherwise profiles can switch them on or off
+        if unset("match_path") {
+            args.match_path = self.match_path.unwrap_or(args.match_path);
+        }
+        if unset("ignore_case") {
+            args.ignore_case = self.ignore_case.unwrap_or(args.ignore_case);
+        }
+        if unset("report_duplicates") {
+            args.report_duplicates = self.report_duplicates.unwrap_or(args.report_duplicates);
+        }
+        if unset("timings") {
+            args.timings = self.timings.unwrap_or(args.timings);
+        }
+        if unset("link_duplicates") {
+            args.link_duplicates = self.link_duplicates.unwrap_or(args.link_duplicates);
+        }
+        Ok(())
+    }
+}
+
+/// profiles compiled into the binary, can be overridden by configuration files
+fn builtin_profiles() -> BTreeMap<String, Profile> {
+    #[cfg_attr(not(windows), allow(unused_mut))]
+    let mut profiles = BTreeMap::new();
+    #[cfg(windows)]
+    profiles.insert(
+        DEFAULT_PROFILE.to_owned(),
+        Profile {
+            // the windows directory is full of shadow copies, won't save anything and might mess with the OS
+            exclude_dirs: Some(vec!["WINDOWS".to_owned()]),
+            // GOG uninstallers have a stupid locking mechanism that cause a +deadlock during
+            // uninstall when hard linked
+            exclude_files: Some(vec!["unins*".to_owned(), "*.db".to_owned()]),
+            ..Default::default()
+        },
+    );
+    profiles
+}
+
+/// system wide and per user configuration files, in order of increasing precedence
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    #[cfg(windows)]
+    {
+        if let Some(program_data) = std::env::var_os("ProgramData") {
+            paths.push(Path::new(&program_data).join("find_doubles\\config.toml"));
+        }
+        if let Some(app_data) = std::env::var_os("APPDATA") {
+            paths.push(Path::new(&app_data).join("find_doubles\\config.toml"));
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        paths.push(PathBuf::from("/etc/find_doubles.toml"));
+        if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+            paths.push(Path::new(&config_home).join("find_doubles/config.toml"));
+        } else if let Some(home) = std::env::var_os("HOME") {
+            paths.push(Path::new(&home).join(".config/find_doubles/config.toml"));
+        }
+    }
+    paths
+}
+
+fn read_config(path: &Path) -> Result<ConfigFile, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{e} reading {}", path.display()))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// load all configuration files and combine the default profile with the selected one
+/// missing system or user files are fine, a missing explicit file is not
+pub fn load_profile(explicit: Option<&Path>, name: Option<&str>) -> Result<Profile, String> {
+    let mut profiles = builtin_profiles();
+    let mut sources = Vec::new();
+    for path in config_paths() {
+        if path.is_file() {
+            sources.push(read_config(&path)?);
+        }
+    }
+    if let Some(path) = explicit {
+        sources.push(read_config(path)?);
+    }
+    for source in sources {
+        for (profile_name, profile) in source.profiles {
+            profiles.entry(profile_name).or_default().merge(profile);
+        }
+    }
+    let mut result = profiles.get(DEFAULT_PROFILE).cloned().unwrap_or_default();
+    if let Some(name) = name {
+        match profiles.remove(name) {
+            Some(profile) => result.merge(profile),
+            None => {
+                let known: Vec<&String> = profiles.keys().collect();
+                return Err(format!(
+                    "unknown profile '{name}', known profiles: {known:?}"
+                ));
+            }
+        }
+    }
+    Ok(result)
+}
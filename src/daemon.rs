@@ -0,0 +1,273 @@
+use crate::filter::Filter;
+use crate::output::textln;
+use crate::stats::Stats;
+#[cfg(target_os = "linux")]
+use crate::watch::Watcher;
+use crate::{Actions, Args, Index};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// how long to sleep when neither clients nor the file system had anything to do
+const IDLE_INTERVAL: Duration = Duration::from_millis(200);
+
+// longest request line, clients sending more without a newline are disconnected
+const MAX_LINE: usize = 64 << 10;
+
+// most response bytes kept for a client that does not read them, it is disconnected then
+const MAX_QUEUED: usize = 64 << 20;
+
+/// one request per line, e.g. {"command": "duplicates", "path": "/home/deck/games"}
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Request {
+    Rescan,
+    Duplicates { path: PathBuf },
+    Pause,
+    Resume,
+    Stats,
+}
+
+struct Client {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    // responses not written yet, the socket is never blocked on
+    queued: Vec<u8>,
+    // no more requests are read, the client is dropped once its responses are written
+    closing: bool,
+}
+
+impl Client {
+    /// read what the client sent, returns false when the connection failed
+    fn receive(&mut self) -> bool {
+        let mut chunk = [0; 4096];
+        while !self.closing {
+            match self.stream.read(&mut chunk) {
+                // hung up, the requests sent before are still answered
+                Ok(0) => self.closing = true,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// next complete request line, lines that are too long are answered with an error
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        if self.queued.len() > MAX_QUEUED {
+            return None;
+        }
+        match self.buffer.iter().position(|&b| b == b'\n') {
+            Some(end) if end < MAX_LINE => Some(self.buffer.drain(..=end).collect()),
+            None if self.buffer.len() < MAX_LINE => None,
+            _ => {
+                self.buffer.clear();
+                self.closing = true;
+                let error = format!("request longer than {MAX_LINE} bytes");
+                self.reply(&json!({"ok": false, "error": error}));
+                None
+            }
+        }
+    }
+
+    fn reply(&mut self, response: &Value) {
+        self.queued
+            .extend_from_slice(response.to_string().as_bytes());
+        self.queued.push(b'\n');
+    }
+
+    /// write as much of the responses as the socket takes without blocking
+    /// returns false when the client is to be dropped
+    fn flush(&mut self) -> bool {
+        let mut written = 0;
+        while written < self.queued.len() {
+            match self.stream.write(&self.queued[written..]) {
+                Ok(0) => return false,
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        self.queued.drain(..written);
+        self.queued.len() <= MAX_QUEUED && !(self.closing && self.queued.is_empty())
+    }
+}
+
+/// the resident state of the daemon
+struct Daemon<'a> {
+    args: &'a Args,
+    filter: Filter,
+    actions: Actions,
+    index: Index,
+    stats: Stats,
+    paused: bool,
+    #[cfg(target_os = "linux")]
+    watcher: Option<Watcher<'a>>,
+}
+
+/// keep the file index resident and serve requests on a Unix domain socket until killed
+/// directories in args should be absolute so that clients can query them
+pub fn run(
+    args: &Args,
+    filter: &Filter,
+    mut actions: Actions,
+    socket: &Path,
+) -> std::io::Result<()> {
+    // a stale socket of a previous daemon would make bind fail, a live one is left alone
+    match UnixStream::connect(socket) {
+        Ok(_) => {
+            return Err(std::io::Error::new(
+                ErrorKind::AddrInUse,
+                "another daemon is listening on this socket",
+            ))
+        }
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+                if metadata.file_type().is_socket() {
+                    std::fs::remove_file(socket)?;
+                }
+            }
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(socket)?;
+    listener.set_nonblocking(true)?;
+    actions.sets = Some(Vec::new());
+    let (index, stats) = crate::run(args, filter, &mut actions);
+    let mut daemon = Daemon {
+        args,
+        filter: filter.clone(),
+        actions,
+        index,
+        stats,
+        paused: false,
+        #[cfg(target_os = "linux")]
+        watcher: None,
+    };
+    daemon.start_watching();
+    textln!("listening on {}", socket.display());
+    let mut clients: Vec<Client> = Vec::new();
+    loop {
+        let mut busy = false;
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    clients.push(Client {
+                        stream,
+                        buffer: Vec::new(),
+                        queued: Vec::new(),
+                        closing: false,
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        // serve complete request lines, drop clients that hung up or do not read their responses
+        clients.retain_mut(|client| {
+            if !client.receive() {
+                return false;
+            }
+            while let Some(line) = client.next_line() {
+                busy = true;
+                let response = daemon.handle(&line);
+                client.reply(&response);
+            }
+            client.flush()
+        });
+        #[cfg(target_os = "linux")]
+        if let Some(ref mut watcher) = daemon.watcher {
+            busy |= watcher.poll(&mut daemon.index, &mut daemon.actions)?;
+        }
+        if !busy {
+            std::thread::sleep(IDLE_INTERVAL);
+        }
+    }
+}
+
+impl Daemon<'_> {
+    fn start_watching(&mut self) {
+        #[cfg(target_os = "linux")]
+        if self.args.watch {
+            self.watcher = match Watcher::new(self.args, &self.filter, &self.index) {
+                Ok(mut watcher) => {
+                    watcher.paused = self.paused;
+                    Some(watcher)
+                }
+                Err(e) => {
+                    println!(
+                        "{:?} watching directories, new files are not deduplicated",
+                        e
+                    );
+                    None
+                }
+            };
+        }
+    }
+
+    fn handle(&mut self, line: &[u8]) -> Value {
+        let request: Request = match serde_json::from_slice(line) {
+            Ok(request) => request,
+            Err(e) => return json!({"ok": false, "error": e.to_string()}),
+        };
+        match request {
+            Request::Rescan => {
+                self.filter.now = SystemTime::now();
+                self.actions.sets = Some(Vec::new());
+                #[cfg(target_os = "linux")]
+                {
+                    self.watcher = None;
+                }
+                let (index, stats) = crate::run(self.args, &self.filter, &mut self.actions);
+                self.index = index;
+                self.stats = stats;
+                self.start_watching();
+                json!({"ok": true, "stats": self.stats})
+            }
+            Request::Duplicates { path } => {
+                let sets: Vec<Value> = self
+                    .actions
+                    .sets
+                    .iter()
+                    .flatten()
+                    .filter(|set| set.files.iter().any(|file| file.starts_with(&path)))
+                    .map(|set| json!({"size": set.size, "files": set.files}))
+                    .collect();
+                json!({"ok": true, "sets": sets})
+            }
+            Request::Pause => {
+                // new files wait in the watcher, a rescan while paused only reports
+                self.paused = true;
+                self.actions.link = false;
+                #[cfg(target_os = "linux")]
+                if let Some(ref mut watcher) = self.watcher {
+                    watcher.paused = true;
+                }
+                json!({"ok": true, "paused": true})
+            }
+            Request::Resume => {
+                self.paused = false;
+                self.actions.link = self.args.link_duplicates;
+                #[cfg(target_os = "linux")]
+                if let Some(ref mut watcher) = self.watcher {
+                    watcher.paused = false;
+                }
+                json!({"ok": true, "paused": false})
+            }
+            Request::Stats => json!({
+                "ok": true,
+                "paused": self.paused,
+                "files": self.index.files.len(),
+                "stats": self.stats,
+            }),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// _IOWR('f', 11, struct fiemap) from linux/fs.h
+const FS_IOC_FIEMAP: u64 = 0xc020_660b;
+
+/// last extent of the file
+pub const FIEMAP_EXTENT_LAST: u32 = 0x0001;
+/// location of the data is not known yet, e.g. delayed allocation
+pub const FIEMAP_EXTENT_UNKNOWN: u32 = 0x0002;
+/// the extent is shared with other files, e.g. reflink clones or snapshots
+pub const FIEMAP_EXTENT_SHARED: u32 = 0x2000;
+
+#[repr(C)]
+struct Header {
+    start: u64,
+    length: u64,
+    flags: u32,
+    mapped_extents: u32,
+    extent_count: u32,
+    reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct RawExtent {
+    logical: u64,
+    physical: u64,
+    length: u64,
+    reserved64: [u64; 2],
+    flags: u32,
+    reserved: [u32; 3],
+}
+
+#[repr(C)]
+struct Request<const N: usize> {
+    header: Header,
+    extents: [RawExtent; N],
+}
+
+/// a contiguous part of a file on disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extent {
+    pub logical: u64,
+    pub physical: u64,
+    pub length: u64,
+    pub flags: u32,
+}
+
+/// up to N extents of file starting at the logical offset start
+fn query<const N: usize>(file: &File, start: u64) -> std::io::Result<Vec<Extent>> {
+    let mut request = Request::<N> {
+        header: Header {
+            start,
+            length: u64::MAX - start,
+            flags: 0,
+            mapped_extents: 0,
+            extent_count: N as u32,
+            reserved: 0,
+        },
+        extents: [RawExtent::default(); N],
+    };
+    if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut request) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mapped = (request.header.mapped_extents as usize).min(N);
+    Ok(request.extents[..mapped]
+        .iter()
+        .map(|extent| Extent {
+            logical: extent.logical,
+            physical: extent.physical,
+            length: extent.length,
+            flags: extent.flags,
+        })
+        .collect())
+}
+
+/// position of the beginning of the file on disk, None for file systems without FIEMAP,
+/// empty files and data that was not written to disk yet
+pub fn first_physical(file: &File) -> Option<u64> {
+    let extent = *query::<1>(file, 0).ok()?.first()?;
+    (extent.flags & FIEMAP_EXTENT_UNKNOWN == 0).then_some(extent.physical)
+}
+
+// extents fetched per ioctl
+const BATCH: usize = 32;
+
+/// all extents of file in logical order
+pub fn extents(file: &File) -> std::io::Result<Vec<Extent>> {
+    let mut extents = Vec::new();
+    let mut start = 0;
+    loop {
+        let batch = query::<BATCH>(file, start)?;
+        let Some(last) = batch.last().copied() else {
+            return Ok(extents);
+        };
+        extents.extend_from_slice(&batch);
+        if last.flags & FIEMAP_EXTENT_LAST != 0 {
+            return Ok(extents);
+        }
+        start = last.logical + last.length;
+    }
+}
+
+/// the file system of path can share extents between files, i.e. it supports reflinks
+pub fn may_share_extents(path: &Path) -> bool {
+    const REFLINK_MAGICS: [u64; 3] = [
+        0x9123683e, // btrfs
+        0x58465342, // XFS
+        0xca451a4e, // bcachefs
+    ];
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    let magic = stat.f_type as u64;
+    REFLINK_MAGICS.contains(&magic)
+}
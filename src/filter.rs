@@ -0,0 +1,187 @@
+use crate::Args;
+use clap::ValueEnum;
+use regex::{Regex, RegexBuilder};
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+/// file time used to determine the age of a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AgeBy {
+    /// time of last content modification
+    Mtime,
+    /// time of last status change (creation time on Windows)
+    Ctime,
+}
+
+/// a name pattern in GLOB syntax, or a regular expression when prefixed with `re:`
+#[derive(Clone, Debug)]
+pub enum NamePattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    /// parse a command line pattern
+    pub fn parse(text: &str) -> Result<NamePattern, String> {
+        match text.strip_prefix("re:") {
+            Some(regex) => Regex::new(regex)
+                .map(NamePattern::Regex)
+                .map_err(|e| e.to_string()),
+            None => glob::Pattern::new(text)
+                .map(NamePattern::Glob)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// case-insensitive version of this pattern
+    // globs get their case sensitivity at match time, regular expressions have to be rebuilt
+    pub fn ignoring_case(self) -> NamePattern {
+        match self {
+            NamePattern::Regex(regex) => NamePattern::Regex(
+                RegexBuilder::new(regex.as_str())
+                    .case_insensitive(true)
+                    .build()
+                    .expect("regex was valid before"),
+            ),
+            glob => glob,
+        }
+    }
+
+    fn matches(&self, text: &str, ignore_case: bool) -> bool {
+        match self {
+            NamePattern::Glob(glob) => glob.matches_with(
+                text,
+                glob::MatchOptions {
+                    case_sensitive: !ignore_case,
+                    ..Default::default()
+                },
+            ),
+            NamePattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// patterns as given on the command line, made case-insensitive when requested
+fn case_patterns(patterns: &[NamePattern], ignore_case: bool) -> Vec<NamePattern> {
+    if ignore_case {
+        patterns
+            .iter()
+            .cloned()
+            .map(NamePattern::ignoring_case)
+            .collect()
+    } else {
+        patterns.to_vec()
+    }
+}
+
+/// criteria a file or directory has to meet to be considered during the scan
+#[derive(Clone)]
+pub struct Filter {
+    pub min_size: u64,
+    pub max_size: u64,
+    pub min_age: Option<Duration>,
+    pub max_age: Option<Duration>,
+    pub age_by: AgeBy,
+    pub include_files: Vec<NamePattern>,
+    pub exclude_files: Vec<NamePattern>,
+    pub exclude_dirs: Vec<NamePattern>,
+    // match patterns against the full path instead of the bare name
+    pub match_path: bool,
+    pub ignore_case: bool,
+    // reference point for all age computations, taken once so the scan is consistent
+    pub now: SystemTime,
+}
+
+impl Filter {
+    pub fn new(args: &Args) -> Filter {
+        Filter {
+            min_size: args.min_size,
+            max_size: args.max_size,
+            min_age: args.min_age,
+            max_age: args.max_age,
+            age_by: args.age_by,
+            include_files: case_patterns(&args.include, args.ignore_case),
+            exclude_files: case_patterns(&args.exclude_files, args.ignore_case),
+            exclude_dirs: case_patterns(&args.exclude_dirs, args.ignore_case),
+            match_path: args.match_path,
+            ignore_case: args.ignore_case,
+            now: SystemTime::now(),
+        }
+    }
+
+    /// check name patterns of any directory entry, excludes always take precedence
+    // include patterns only apply to files, otherwise we could not descend into directories
+    pub fn accepts_name(&self, path: &Path, is_file: bool) -> bool {
+        let text = if self.match_path {
+            path.to_string_lossy()
+        } else {
+            path.file_name().unwrap_or_default().to_string_lossy()
+        };
+        let matches = |patterns: &Vec<NamePattern>| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches(&text, self.ignore_case))
+        };
+        if matches(&self.exclude_files) {
+            return false;
+        }
+        if is_file {
+            self.include_files.is_empty() || matches(&self.include_files)
+        } else {
+            !matches(&self.exclude_dirs)
+        }
+    }
+
+    /// check size and age limits of a regular file
+    pub fn accepts_file(&self, metadata: &Metadata) -> bool {
+        self.accepts_size_time(metadata.len(), file_time(metadata, self.age_by))
+    }
+
+    /// check size and age limits, time is the file time selected by age_by
+    pub fn accepts_size_time(&self, size: u64, time: Option<SystemTime>) -> bool {
+        if size < self.min_size || size > self.max_size {
+            return false;
+        }
+        if self.min_age.is_none() && self.max_age.is_none() {
+            return true;
+        }
+        // age relative to the start of the scan, files from the future have age 0
+        let age = match time {
+            Some(time) => self.now.duration_since(time).unwrap_or_default(),
+            // play it safe, files of unknown age might still be written to
+            None => return self.min_age.is_none(),
+        };
+        if let Some(min_age) = self.min_age {
+            if age < min_age {
+                return false;
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            if age > max_age {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// file time used for age checks
+pub fn file_time(metadata: &Metadata, age_by: AgeBy) -> Option<SystemTime> {
+    match age_by {
+        AgeBy::Mtime => metadata.modified().ok(),
+        #[cfg(unix)]
+        AgeBy::Ctime => {
+            let seconds = u64::try_from(metadata.ctime()).ok()?;
+            Some(
+                SystemTime::UNIX_EPOCH
+                    + Duration::new(seconds, metadata.ctime_nsec().clamp(0, 999_999_999) as u32),
+            )
+        }
+        #[cfg(not(unix))]
+        AgeBy::Ctime => metadata.created().ok(),
+    }
+}
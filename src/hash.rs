@@ -0,0 +1,169 @@
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
+
+/// hash of the initial segment of a file, has to implement Ord for sorting
+pub type PeekHash = u128;
+
+/// hash of the whole file content, wide enough for all full hash algorithms
+/// shorter digests are padded with zeros
+pub type FullHash = [u8; 32];
+
+/// algorithm used for hashing the initial segment of files
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PeekAlgorithm {
+    Murmur3,
+    Xxh3,
+}
+
+/// algorithm used for hashing the whole file content
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FullAlgorithm {
+    Blake3,
+    // blake3 with a secret key, others can't craft files with colliding hashes
+    Blake3Keyed,
+    // for comparing with checksums of external manifests
+    Sha256,
+    Xxh3,
+}
+
+impl PeekAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            PeekAlgorithm::Murmur3 => "murmur3-128",
+            PeekAlgorithm::Xxh3 => "xxh3-128",
+        }
+    }
+}
+
+impl FullAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            FullAlgorithm::Blake3 => "blake3",
+            FullAlgorithm::Blake3Keyed => "blake3-keyed",
+            FullAlgorithm::Sha256 => "sha256",
+            FullAlgorithm::Xxh3 => "xxh3-128",
+        }
+    }
+
+    /// number of significant bytes of the full hash
+    pub fn digest_len(self) -> usize {
+        match self {
+            FullAlgorithm::Xxh3 => 16,
+            _ => 32,
+        }
+    }
+
+    /// colliding files can't be crafted, equal hashes may stand for equal content
+    pub fn is_cryptographic(self) -> bool {
+        self != FullAlgorithm::Xxh3
+    }
+
+    /// stable number of the algorithm, e.g. for index files
+    pub fn id(self) -> u32 {
+        match self {
+            FullAlgorithm::Blake3 => 0,
+            FullAlgorithm::Blake3Keyed => 1,
+            FullAlgorithm::Sha256 => 2,
+            FullAlgorithm::Xxh3 => 3,
+        }
+    }
+}
+
+struct Algorithms {
+    peek: PeekAlgorithm,
+    full: FullAlgorithm,
+    key: [u8; 32],
+}
+
+// chosen once at startup, hashes are computed deep inside the grouping and watch code
+static ALGORITHMS: OnceLock<Algorithms> = OnceLock::new();
+
+// context string of the blake3 key derivation, changing it changes all keyed hashes
+const KEY_CONTEXT: &str = "find_doubles 2024-06 full hash key";
+
+/// select the hash algorithms, key is any secret for blake3-keyed
+pub fn init(peek: PeekAlgorithm, full: FullAlgorithm, key: Option<&[u8]>) {
+    let key = key.map_or([0; 32], |key| blake3::derive_key(KEY_CONTEXT, key));
+    let _ = ALGORITHMS.set(Algorithms { peek, full, key });
+}
+
+fn algorithms() -> &'static Algorithms {
+    ALGORITHMS.get_or_init(|| Algorithms {
+        peek: PeekAlgorithm::Murmur3,
+        full: FullAlgorithm::Blake3,
+        key: [0; 32],
+    })
+}
+
+pub fn peek_algorithm() -> PeekAlgorithm {
+    algorithms().peek
+}
+
+pub fn full_algorithm() -> FullAlgorithm {
+    algorithms().full
+}
+
+/// hash of the initial segment of a file
+pub fn peek(data: &[u8]) -> PeekHash {
+    match algorithms().peek {
+        PeekAlgorithm::Murmur3 => fastmurmur3::hash(data),
+        PeekAlgorithm::Xxh3 => xxh3_128(data),
+    }
+}
+
+/// full hash of the empty input, it differs between algorithms and keys
+/// and tells whether stored hashes were computed the same way
+pub fn fingerprint() -> FullHash {
+    FullHasher::new().finalize()
+}
+
+/// incremental full hash with the selected algorithm
+pub enum FullHasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Default for FullHasher {
+    fn default() -> FullHasher {
+        FullHasher::new()
+    }
+}
+
+impl FullHasher {
+    pub fn new() -> FullHasher {
+        let algorithms = algorithms();
+        match algorithms.full {
+            FullAlgorithm::Blake3 => FullHasher::Blake3(Box::new(blake3::Hasher::new())),
+            FullAlgorithm::Blake3Keyed => {
+                FullHasher::Blake3(Box::new(blake3::Hasher::new_keyed(&algorithms.key)))
+            }
+            FullAlgorithm::Sha256 => FullHasher::Sha256(Sha256::new()),
+            FullAlgorithm::Xxh3 => FullHasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            FullHasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            FullHasher::Sha256(hasher) => hasher.update(data),
+            FullHasher::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> FullHash {
+        match self {
+            FullHasher::Blake3(hasher) => *hasher.finalize().as_bytes(),
+            FullHasher::Sha256(hasher) => hasher.finalize().into(),
+            FullHasher::Xxh3(hasher) => {
+                let mut hash = FullHash::default();
+                hash[..16].copy_from_slice(&hasher.digest128().to_be_bytes());
+                hash
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+/// per-directory ignore files in gitignore syntax, inherited down the directory tree
+pub struct IgnoreStack {
+    file_name: String,
+    // one matcher per directory on the current path that has an ignore file, outermost first
+    matchers: Vec<Gitignore>,
+}
+
+impl IgnoreStack {
+    pub fn new(file_name: &str) -> IgnoreStack {
+        IgnoreStack {
+            file_name: file_name.to_owned(),
+            matchers: Vec::new(),
+        }
+    }
+
+    /// load the ignore file of dir if there is one
+    /// returns true when a matcher was added, which has to be removed with leave() afterwards
+    pub fn enter(&mut self, dir: &Path) -> bool {
+        let ignore_path = dir.join(&self.file_name);
+        if !ignore_path.is_file() {
+            return false;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&ignore_path) {
+            eprintln!(
+                "{e} reading {}, some patterns are not used",
+                ignore_path.display()
+            );
+        }
+        match builder.build() {
+            Ok(matcher) => {
+                self.matchers.push(matcher);
+                true
+            }
+            Err(e) => {
+                eprintln!("{e} in {}, file is not used", ignore_path.display());
+                false
+            }
+        }
+    }
+
+    pub fn leave(&mut self) {
+        self.matchers.pop();
+    }
+
+    /// the innermost ignore file with a matching pattern decides, so negations can re-include entries
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for matcher in self.matchers.iter().rev() {
+            let matched = matcher.matched(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,337 @@
+use crate::hash::{self, FullHash};
+use crate::replace::replace_file;
+use crate::scan_cache::{path_bytes, path_from_bytes};
+use crate::{FileId, FileInfo, HashCache, Index};
+use memmap::Mmap;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// layout, all numbers little endian:
+// header: magic, version, number of directories, files and hashes, size of the string pool,
+//         id of the full hash algorithm and its hash of the empty input
+// directories: offset and length of the path in the string pool
+// files: directory index, size, id (128 bit on all platforms), offset and length of the name
+// hashes: id, size and full hash of files hashed so far
+// string pool: all paths and names without separators
+const MAGIC: &[u8; 4] = b"FDIX";
+const VERSION: u32 = 2;
+const HEADER_SIZE: usize = 80;
+const DIR_SIZE: usize = 16;
+const FILE_SIZE: usize = 48;
+const HASH_SIZE: usize = 24 + std::mem::size_of::<FullHash>();
+
+/// write the file list, directories and known full hashes to file
+fn write(index: &Index, file: &mut File) -> std::io::Result<()> {
+    let mut strings: Vec<u8> = Vec::new();
+    let mut writer = BufWriter::new(file);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(index.all_dirs.len() as u64).to_le_bytes())?;
+    writer.write_all(&(index.files.len() as u64).to_le_bytes())?;
+    writer.write_all(&(index.full_hash_cache.len() as u64).to_le_bytes())?;
+    let strings_len: usize = index
+        .all_dirs
+        .iter()
+        .map(|dir| path_bytes(dir.as_os_str()).len())
+        .chain(index.files.iter().map(|file| path_bytes(&file.name).len()))
+        .sum();
+    writer.write_all(&(strings_len as u64).to_le_bytes())?;
+    writer.write_all(&u64::from(hash::full_algorithm().id()).to_le_bytes())?;
+    writer.write_all(&hash::fingerprint())?;
+    let mut add_string = |writer: &mut BufWriter<&mut File>, bytes: &[u8]| {
+        writer.write_all(&(strings.len() as u64).to_le_bytes())?;
+        writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        strings.extend_from_slice(bytes);
+        Ok::<(), Error>(())
+    };
+    for dir in &index.all_dirs {
+        add_string(&mut writer, &path_bytes(dir.as_os_str()))?;
+    }
+    for file in &index.files {
+        writer.write_all(&(file.dir_index as u64).to_le_bytes())?;
+        writer.write_all(&file.size.to_le_bytes())?;
+        writer.write_all(&u128::from(file.id).to_le_bytes())?;
+        add_string(&mut writer, &path_bytes(&file.name))?;
+    }
+    for ((id, size), hash) in &index.full_hash_cache {
+        writer.write_all(&u128::from(*id).to_le_bytes())?;
+        writer.write_all(&size.to_le_bytes())?;
+        writer.write_all(hash)?;
+    }
+    writer.write_all(&strings)?;
+    writer.flush()
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+/// memory maps an index file and checks its layout
+struct IndexMap {
+    map: Mmap,
+    dirs: usize,
+    files: usize,
+    hashes: usize,
+    // the hashes were computed with the current algorithm and key
+    same_hashes: bool,
+}
+
+impl IndexMap {
+    fn open(path: &Path) -> std::io::Result<IndexMap> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() < HEADER_SIZE as u64 {
+            return Err(invalid("index file too short"));
+        }
+        // the index is only read, a concurrent writer could still truncate it but we only
+        // write index files by replacing them
+        let map = unsafe { Mmap::map(&file)? };
+        let mut index_map = IndexMap {
+            map,
+            dirs: 0,
+            files: 0,
+            hashes: 0,
+            same_hashes: false,
+        };
+        if &index_map.map[..4] != MAGIC {
+            return Err(invalid("not an index file"));
+        }
+        let version = u32::from_le_bytes(index_map.map[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported index version {version}")));
+        }
+        let count = |i: usize| usize::try_from(index_map.u64_at(8 + 8 * i)).ok();
+        let (dirs, files, hashes, strings) = match (count(0), count(1), count(2), count(3)) {
+            (Some(dirs), Some(files), Some(hashes), Some(strings)) => {
+                (dirs, files, hashes, strings)
+            }
+            _ => return Err(invalid("index file too large")),
+        };
+        let expected = dirs
+            .checked_mul(DIR_SIZE)
+            .zip(files.checked_mul(FILE_SIZE))
+            .zip(hashes.checked_mul(HASH_SIZE))
+            .and_then(|((d, f), h)| HEADER_SIZE.checked_add(d)?.checked_add(f)?.checked_add(h))
+            .and_then(|tables| tables.checked_add(strings));
+        if expected != Some(index_map.map.len()) {
+            return Err(invalid("index file has wrong size"));
+        }
+        index_map.dirs = dirs;
+        index_map.files = files;
+        index_map.hashes = hashes;
+        index_map.same_hashes = index_map.u64_at(40) == u64::from(hash::full_algorithm().id())
+            && index_map.map[48..80] == hash::fingerprint();
+        Ok(index_map)
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.map[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn u128_at(&self, offset: usize) -> u128 {
+        u128::from_le_bytes(self.map[offset..offset + 16].try_into().unwrap())
+    }
+
+    fn files_start(&self) -> usize {
+        HEADER_SIZE + self.dirs * DIR_SIZE
+    }
+
+    fn hashes_start(&self) -> usize {
+        self.files_start() + self.files * FILE_SIZE
+    }
+
+    fn strings_start(&self) -> usize {
+        self.hashes_start() + self.hashes * HASH_SIZE
+    }
+
+    /// string pool slice referenced by the offset and length at the given position
+    fn string(&self, offset: usize) -> std::io::Result<&[u8]> {
+        let start = usize::try_from(self.u64_at(offset)).unwrap_or(usize::MAX);
+        let len = usize::try_from(self.u64_at(offset + 8)).unwrap_or(usize::MAX);
+        let pool = &self.map[self.strings_start()..];
+        let range: Range<usize> = start..start.saturating_add(len);
+        pool.get(range)
+            .ok_or_else(|| invalid("string out of range"))
+    }
+
+    fn file_id(&self, offset: usize) -> std::io::Result<FileId> {
+        FileId::try_from(self.u128_at(offset)).map_err(|_| invalid("file id too large"))
+    }
+}
+
+/// read an index file written by save()
+pub fn load(path: &Path) -> std::io::Result<Index> {
+    let index_map = IndexMap::open(path)?;
+    let mut all_dirs = Vec::with_capacity(index_map.dirs);
+    for i in 0..index_map.dirs {
+        let bytes = index_map.string(HEADER_SIZE + i * DIR_SIZE)?;
+        all_dirs.push(PathBuf::from(path_from_bytes(bytes.to_vec())));
+    }
+    let mut files = Vec::with_capacity(index_map.files);
+    for i in 0..index_map.files {
+        let offset = index_map.files_start() + i * FILE_SIZE;
+        let dir_index = index_map.u64_at(offset) as usize;
+        if dir_index >= all_dirs.len() {
+            return Err(invalid("directory index out of range"));
+        }
+        let name = path_from_bytes(index_map.string(offset + 32)?.to_vec());
+        files.push(FileInfo {
+            dir_index,
+            size: index_map.u64_at(offset + 8),
+            id: index_map.file_id(offset + 16)?,
+            name,
+        });
+    }
+    // hashes of another algorithm or key are useless, the files are hashed again when needed
+    let hashes = if index_map.same_hashes {
+        index_map.hashes
+    } else {
+        0
+    };
+    let mut full_hash_cache = HashCache::with_capacity(hashes);
+    for i in 0..hashes {
+        let offset = index_map.hashes_start() + i * HASH_SIZE;
+        let hash: FullHash = index_map.map[offset + 24..offset + HASH_SIZE]
+            .try_into()
+            .unwrap();
+        full_hash_cache.insert(
+            (index_map.file_id(offset)?, index_map.u64_at(offset + 16)),
+            hash,
+        );
+    }
+    Ok(Index {
+        all_dirs,
+        files,
+        full_hash_cache,
+    })
+}
+
+/// write the index to path, readers never see a partially written file
+pub fn save(index: &Index, path: &Path) -> std::io::Result<()> {
+    replace_file(path, |file| write(index, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use std::ffi::OsString;
+    use std::fs;
+
+    fn sample_index() -> Index {
+        let file = |dir_index, size, id, name: &str| FileInfo {
+            dir_index,
+            size,
+            id,
+            name: OsString::from(name),
+        };
+        let mut full_hash_cache = HashCache::new();
+        full_hash_cache.insert((7, 100), [3; 32]);
+        Index {
+            all_dirs: vec![PathBuf::from("/data"), PathBuf::from("/data/sub dir")],
+            files: vec![
+                file(0, 100, 7, "a"),
+                file(1, 100, 8, "b.bin"),
+                file(1, 5000, 9, "ünïcode"),
+            ],
+            full_hash_cache,
+        }
+    }
+
+    // the directory with the saved sample index and the content of the index file
+    fn saved(name: &str) -> (TestDir, Vec<u8>) {
+        let dir = TestDir::new(name);
+        save(&sample_index(), &dir.join("index")).unwrap();
+        let bytes = fs::read(dir.join("index")).unwrap();
+        (dir, bytes)
+    }
+
+    fn load_bytes(dir: &TestDir, bytes: &[u8]) -> std::io::Result<Index> {
+        fs::write(dir.join("index"), bytes).unwrap();
+        load(&dir.join("index"))
+    }
+
+    #[test]
+    fn round_trip() {
+        let (dir, _) = saved("index_round_trip");
+        let index = load(&dir.join("index")).unwrap();
+        let expected = sample_index();
+        assert_eq!(index.all_dirs, expected.all_dirs);
+        assert_eq!(index.files.len(), expected.files.len());
+        for (loaded, expected) in index.files.iter().zip(&expected.files) {
+            assert_eq!(loaded.dir_index, expected.dir_index);
+            assert_eq!(loaded.size, expected.size);
+            assert_eq!(loaded.id, expected.id);
+            assert_eq!(loaded.name, expected.name);
+        }
+        assert_eq!(index.full_hash_cache, expected.full_hash_cache);
+        // no temporary file is left behind
+        assert_eq!(dir.entries(), vec!["index"]);
+    }
+
+    #[test]
+    fn hashes_of_other_algorithms_are_dropped() {
+        let (dir, mut bytes) = saved("index_other_algorithm");
+        bytes[48] ^= 1;
+        let index = load_bytes(&dir, &bytes).unwrap();
+        assert_eq!(index.files.len(), 3);
+        assert!(index.full_hash_cache.is_empty());
+    }
+
+    #[test]
+    fn wrong_magic_or_version() {
+        let (dir, bytes) = saved("index_magic");
+        let mut wrong = bytes.clone();
+        wrong[0] = b'X';
+        assert!(load_bytes(&dir, &wrong).is_err());
+        let mut wrong = bytes;
+        wrong[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(load_bytes(&dir, &wrong).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let (dir, bytes) = saved("index_truncated");
+        for len in [0, 3, HEADER_SIZE - 1, HEADER_SIZE, bytes.len() - 1] {
+            assert!(load_bytes(&dir, &bytes[..len]).is_err(), "{len}");
+        }
+        let mut longer = bytes;
+        longer.push(0);
+        assert!(load_bytes(&dir, &longer).is_err());
+    }
+
+    #[test]
+    fn counts_out_of_range() {
+        let (dir, bytes) = saved("index_counts");
+        for field in 0..4 {
+            let offset = 8 + 8 * field;
+            let mut wrong = bytes.clone();
+            wrong[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+            assert!(load_bytes(&dir, &wrong).is_err(), "{field}");
+        }
+    }
+
+    #[test]
+    fn strings_out_of_range() {
+        let (dir, bytes) = saved("index_strings");
+        let first_name = HEADER_SIZE + 2 * DIR_SIZE + 32;
+        for (offset, value) in [
+            // offset of the first directory
+            (HEADER_SIZE, 1 << 20),
+            (HEADER_SIZE, u64::MAX),
+            // length of the first directory
+            (HEADER_SIZE + 8, 1 << 20),
+            (HEADER_SIZE + 8, u64::MAX),
+            // offset and length of the first file name
+            (first_name, u64::MAX),
+            (first_name + 8, u64::MAX),
+            // directory index of the first file
+            (HEADER_SIZE + 2 * DIR_SIZE, 2),
+        ] {
+            let mut wrong = bytes.clone();
+            wrong[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            assert!(load_bytes(&dir, &wrong).is_err(), "{offset} {value}");
+        }
+    }
+}
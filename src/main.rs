@@ -1,11 +1,51 @@
-use clap::Parser;
-use memmap::Mmap;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
+mod config;
+#[cfg(unix)]
+mod daemon;
+#[cfg(target_os = "linux")]
+mod fiemap;
+mod filter;
+mod hash;
+mod ignore_files;
+mod index_file;
+#[cfg(target_os = "linux")]
+mod mmap_guard;
+mod output;
+mod progress;
+mod reader;
+mod replace;
+mod restore;
+mod roots;
+mod scan_cache;
+mod sqlite_export;
+mod stats;
+#[cfg(test)]
+mod test_dir;
+mod units;
+#[cfg(target_os = "linux")]
+mod watch;
+
+use filter::{AgeBy, Filter, NamePattern};
+use hash::{FullAlgorithm, FullHash, FullHasher, PeekAlgorithm, PeekHash};
+use ignore_files::IgnoreStack;
+use output::textln;
+use progress::Phase;
+use reader::{IoMode, Reader};
+use roots::Roots;
+use scan_cache::{CachedFile, ScanCache};
+use stats::{Stats, StatsFormat};
+use units::kmgt;
+
 // get inode on unix and Linux as unique file id
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
@@ -13,53 +53,250 @@ use std::os::unix::fs::MetadataExt;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// minimum file size
-    #[arg(short = 'm', long, value_name = "BYTES", default_value_t = 65536)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// read profiles from this configuration file in addition to the system and user ones
+    #[arg(long, value_name = "FILE.toml", global = true)]
+    config: Option<PathBuf>,
+
+    /// use options of this configuration profile, command line options take precedence
+    #[arg(long, value_name = "NAME", global = true)]
+    profile: Option<String>,
+
+    /// minimum file size, e.g. 64K, 1.5MiB or 2GB
+    #[arg(short = 'm', long, value_name = "SIZE", default_value = "64KiB", value_parser = units::parse_size, global = true)]
     min_size: u64,
 
-    /// maximum file size
-    #[arg(short='M',long, value_name="BYTES", default_value_t = std::u64::MAX)]
+    /// maximum file size, e.g. 64K, 1.5MiB or 2GB
+    #[arg(short='M',long, value_name="SIZE", default_value_t = std::u64::MAX, value_parser = units::parse_size, global = true)]
     max_size: u64,
 
     /// length of initial segment to hash when more than 2 files have the same length
-    #[arg(short = 'H', long, value_name = "BYTES", default_value_t = 4096)]
+    #[arg(short = 'H', long, value_name = "SIZE", default_value = "4KiB", value_parser = units::parse_size, global = true)]
     peek_hash: u64,
 
-    /// directory to be scanned, can be repeated
-    #[arg(short, long)]
+    /// parts hashed after the initial segment to tell apart more than 2 files before hashing them fully, none without a value
+    #[arg(long, value_name = "STAGES", value_enum, value_delimiter = ',', num_args = 0.., default_value = "tail", global = true)]
+    sample_stages: Vec<SampleStage>,
+
+    /// algorithm for hashing the initial segment
+    #[arg(
+        long,
+        value_name = "ALGORITHM",
+        value_enum,
+        default_value = "murmur3",
+        global = true
+    )]
+    peek_algorithm: PeekAlgorithm,
+
+    /// algorithm for hashing the whole content when more than 2 files are alike
+    #[arg(
+        long,
+        value_name = "ALGORITHM",
+        value_enum,
+        default_value = "blake3",
+        global = true
+    )]
+    hash_algorithm: FullAlgorithm,
+
+    /// file with the secret the key of --hash-algorithm blake3-keyed is derived from
+    #[arg(
+        long,
+        value_name = "FILE",
+        required_if_eq("hash_algorithm", "blake3-keyed"),
+        global = true
+    )]
+    hash_key_file: Option<PathBuf>,
+
+    /// how file contents are read, auto uses read() on network file systems and memory mapping otherwise
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_enum,
+        default_value = "auto",
+        global = true
+    )]
+    io: IoMode,
+
+    /// buffer size of read(), e.g. 256K or 4MiB
+    #[arg(long, value_name = "SIZE", default_value = "1MiB", value_parser = units::parse_size, global = true)]
+    buffer_size: u64,
+
+    /// optimize for spinning disks: work through files in their order on disk and read large chunks
+    #[arg(long, global = true)]
+    hdd: bool,
+
+    /// skip files younger than this, e.g. 12h or 7d
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration, global = true)]
+    min_age: Option<Duration>,
+
+    /// skip files older than this, e.g. 12h or 7d
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration, global = true)]
+    max_age: Option<Duration>,
+
+    /// file time used for --min-age and --max-age
+    #[arg(long, value_name = "TIME", value_enum, default_value_t = AgeBy::Mtime, global = true)]
+    age_by: AgeBy,
+
+    /// directory to be scanned, can be repeated, prefix with ro: to never replace its files
+    #[arg(short, long, global = true)]
     directories: Vec<PathBuf>,
 
-    /// files to be excluded from scan, GLOB syntax
-    #[arg(short = 'e', long, value_name = "GLOB")]
-    exclude_files: Vec<glob::Pattern>,
+    /// reference directory whose files are never replaced, only their duplicates are reported and linked, can be repeated
+    #[arg(long, value_name = "DIR", global = true)]
+    reference: Vec<PathBuf>,
+
+    // roots of directories given as -d ro:DIR
+    #[arg(skip)]
+    read_only: Vec<PathBuf>,
+
+    /// only files matching one of these patterns are scanned, GLOB syntax or re:REGEX
+    #[arg(short = 'i', long, value_name = "GLOB", value_parser = NamePattern::parse, global = true)]
+    include: Vec<NamePattern>,
+
+    /// files to be excluded from scan, GLOB syntax or re:REGEX
+    #[arg(short = 'e', long, value_name = "GLOB", value_parser = NamePattern::parse, global = true)]
+    exclude_files: Vec<NamePattern>,
+
+    /// directories to be excluded from scan, GLOB syntax or re:REGEX
+    #[arg(short = 'E', long, value_name = "GLOB", value_parser = NamePattern::parse, global = true)]
+    exclude_dirs: Vec<NamePattern>,
+
+    /// name of per-directory ignore files in gitignore syntax
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = ".find_doubles_ignore",
+        global = true
+    )]
+    ignore_file: String,
+
+    /// match patterns against the full path instead of the file name
+    #[arg(long, global = true)]
+    match_path: bool,
 
-    /// directories to be excluded from scan, GLOB syntax
-    #[arg(short = 'E', long, value_name = "GLOB")]
-    exclude_dirs: Vec<glob::Pattern>,
+    /// match patterns case-insensitively
+    #[arg(long, global = true)]
+    ignore_case: bool,
 
     /// write list of duplicates to CSV file
-    #[arg(short, long, value_name = "FILE.csv")]
+    #[arg(short, long, value_name = "FILE.csv", global = true)]
     csv_export: Option<PathBuf>,
 
     /// report duplicate files
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     report_duplicates: bool,
 
+    /// keep the scan result in this file and only re-read directories that changed since the last run
+    #[arg(long, value_name = "FILE", global = true)]
+    scan_cache: Option<PathBuf>,
+
+    /// write the file list and computed hashes to this index file
+    #[arg(long, value_name = "FILE", global = true)]
+    save_index: Option<PathBuf>,
+
+    /// take the file list from an index file written by --save-index instead of scanning
+    #[arg(long, value_name = "FILE", global = true)]
+    index: Option<PathBuf>,
+
+    /// write directories, files, hashes, duplicate sets and actions taken to this SQLite database
+    #[arg(long, value_name = "FILE", global = true)]
+    sqlite: Option<PathBuf>,
+
     /// print files that matched filter
-    #[arg(short = 'p', long)]
+    #[arg(short = 'p', long, global = true)]
     print_files: bool,
 
     /// print directories
-    #[arg(short = 'P', long)]
+    #[arg(short = 'P', long, global = true)]
     print_directories: bool,
 
+    /// print duplicate sets as NUL terminated paths with an extra NUL after each set, also for -p and -P
+    #[arg(short = '0', long, global = true)]
+    print0: bool,
+
+    /// print duplicate sets like fdupes, one path per line and sets separated by blank lines
+    #[arg(long, global = true)]
+    fdupes: bool,
+
+    /// print the size of the files before each set in fdupes output
+    #[arg(long, requires = "fdupes", global = true)]
+    size: bool,
+
+    /// print only a summary of the duplicates like fdupes --summarize
+    #[arg(long, global = true)]
+    summarize: bool,
+
+    /// print counters and timings in this machine readable format at the end of a run
+    #[arg(long, value_name = "FORMAT", value_enum, global = true)]
+    stats_format: Option<StatsFormat>,
+
+    /// write the statistics of --stats-format to this file instead
+    #[arg(long, value_name = "FILE", requires = "stats_format", global = true)]
+    stats_file: Option<PathBuf>,
+
+    /// print neither progress nor totals
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
     /// print elapsed times
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     timings: bool,
 
     /// replace duplicates by hard links
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     link_duplicates: bool,
+
+    /// byte-compare every file against the kept one before acting, also when the full hashes match
+    #[arg(long, conflicts_with = "trust_hash", global = true)]
+    paranoid: bool,
+
+    /// accept equal full hashes as proof of equal content, also for pairs of files
+    #[arg(long, global = true)]
+    trust_hash: bool,
+
+    /// keep running and deduplicate new files as they appear (Linux only)
+    #[arg(long, global = true)]
+    watch: bool,
+
+    /// time a new file has to stay closed and unchanged before it is deduplicated in watch mode
+    #[arg(long, value_name = "DURATION", default_value = "30s", value_parser = units::parse_duration, global = true)]
+    settle: Duration,
+
+    /// keep the index resident and serve JSON requests on this Unix domain socket
+    #[arg(long, value_name = "SOCKET", global = true)]
+    daemon: Option<PathBuf>,
+
+    /// print sizes in decimal SI units (kB, MB, ...) instead of binary units (kiB, MiB, ...)
+    #[arg(long, global = true)]
+    si: bool,
+}
+
+/// segments of a file that are hashed between the peek hash and the full hash
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum SampleStage {
+    // last segment, archives often only differ in their index at the end
+    Tail,
+    // segment in the middle
+    Middle,
+    // segments at fixed offsets spread over the whole file
+    Spread,
+}
+
+/// separate steps of a run, without a subcommand the flags decide what happens
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq)]
+enum Command {
+    /// only list the files without comparing them, use --save-index to analyse them later
+    Scan,
+    /// report duplicates, same as -r
+    Report,
+    /// replace duplicates by hard links, same as -l
+    Link,
+    /// replace hard links among the scanned files by independent copies
+    Restore,
+    /// print counters and timings of the duplicate search, same as -t
+    Stats,
 }
 
 // under Windows, we use a 128bit murmur3 hash of the first linked file name to distinguish actual physical files
@@ -71,234 +308,397 @@ type FileId = u128;
 type FileId = u64;
 
 fn main() {
-    let mut args = Args::parse();
-    #[cfg(windows)]
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // options from configuration profiles, the built-in default profile holds the Windows excludes
+    if let Err(e) = config::load_profile(args.config.as_deref(), args.profile.as_deref())
+        .and_then(|profile| profile.apply(&mut args, &matches))
     {
-        // a couple of files/dirs to exclude on Windows platforms by default
-        // the windows directory is full of shadow copies, won't save anything and might mess with the OS
-        if args.exclude_dirs.is_empty() {
-            args.exclude_dirs
-                .push(glob::Pattern::new("WINDOWS").unwrap());
+        Args::command().error(ErrorKind::InvalidValue, e).exit();
+    }
+    // anyone who can place files could craft colliding ones, their hashes only sort candidates
+    if !args.hash_algorithm.is_cryptographic() {
+        if args.trust_hash {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "--trust-hash needs a cryptographic hash, {} is not one",
+                        args.hash_algorithm.name()
+                    ),
+                )
+                .exit();
+        }
+        args.paranoid = true;
+    }
+    units::set_si_units(args.si);
+    // spinning disks prefer few large reads over mapped pages faulted in by small readaheads
+    if args.hdd {
+        if args.io == IoMode::Auto {
+            args.io = IoMode::Read;
+        }
+        if matches.value_source("buffer_size") != Some(ValueSource::CommandLine) {
+            args.buffer_size = reader::HDD_BUFFER_SIZE;
+        }
+    }
+    reader::init(args.io, args.buffer_size);
+    // the secret is not taken from the command line, every user can read that
+    let hash_key = args.hash_key_file.as_ref().map(|path| {
+        let mut key = fs::read(path).unwrap_or_else(|e| {
+            let message = format!("{e} reading hash key file {}", path.display());
+            Args::command().error(ErrorKind::Io, message).exit()
+        });
+        // editors end files with a newline
+        while key.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+            key.pop();
+        }
+        key
+    });
+    hash::init(
+        args.peek_algorithm,
+        args.hash_algorithm,
+        hash_key.as_deref(),
+    );
+    // machine readable output must not be mixed with progress
+    progress::init(!args.quiet && !args.print0 && !args.fdupes && !args.summarize);
+    output::set_machine_readable(args.print0 || args.fdupes || args.summarize);
+    // subcommands override the flags of configuration profiles
+    match args.command {
+        Some(Command::Scan) => {
+            args.report_duplicates = false;
+            args.link_duplicates = false;
+        }
+        Some(Command::Report) => {
+            args.report_duplicates = true;
+            args.link_duplicates = false;
         }
-        // GOG uninstallers have a stupid locking mechanism that cause a +deadlock during
-        // uninstall when hard linked
-        if args.exclude_files.is_empty() {
-            args.exclude_files
-                .push(glob::Pattern::new("unins*").unwrap());
-            args.exclude_files.push(glob::Pattern::new("*.db").unwrap());
+        Some(Command::Link) => args.link_duplicates = true,
+        Some(Command::Stats) => {
+            args.timings = true;
+            args.report_duplicates = false;
+            args.link_duplicates = false;
         }
+        Some(Command::Restore) | None => {}
     }
     // use current directory when no dirs were specified
     if args.directories.is_empty() {
         args.directories.push(PathBuf::from("."));
     }
-    let mut files: Vec<FileInfo> = Vec::new();
-    let mut all_dirs: Vec<PathBuf> = vec![];
-
-    let mut csv_file: Option<File> = if let Some(csv_path) = args.csv_export {
-        match File::create(&csv_path) {
-            Ok(file) => Some(file),
-            Err(e) => {
-                println!("{:?} creating {:?}, CSV output is not written", e, csv_path);
-                None
+    args.read_only = roots::strip_read_only(&mut args.directories);
+    let filter = Filter::new(&args);
+    if args.command == Some(Command::Restore) {
+        let mut index = load_index(&args).unwrap_or_else(|| scan(&args, &filter));
+        restore::run(&args, &mut index);
+        return;
+    }
+    if args.command == Some(Command::Scan) {
+        scan_only(&args, &filter);
+        return;
+    }
+    let mut actions = Actions::new(&args);
+    if let Some(ref socket) = args.daemon {
+        #[cfg(unix)]
+        {
+            // clients query with absolute paths
+            let roots = args.directories.iter_mut().chain(args.reference.iter_mut());
+            for dir in roots.chain(args.read_only.iter_mut()) {
+                if let Ok(absolute) = fs::canonicalize(&dir) {
+                    *dir = absolute;
+                }
+            }
+            if let Err(e) = daemon::run(&args, &filter, actions, socket) {
+                eprintln!("{:?} serving {}, giving up", e, socket.display());
             }
         }
-    } else {
-        None
-    };
-    if let Some(ref mut f) = csv_file {
-        writeln!(f, "File,Size,Duplicate").expect("csv write");
+        #[cfg(not(unix))]
+        eprintln!("daemon mode is only supported on Unix systems");
+        return;
     }
-    let start = Instant::now();
-    for dir in args.directories {
-        find_files(
-            &dir,
-            &mut all_dirs,
-            &mut files,
-            args.min_size,
-            args.max_size,
-            &args.exclude_files,
-            &args.exclude_dirs,
-        );
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables, unused_mut))]
+    let (mut index, _stats) = run(&args, &filter, &mut actions);
+    if args.watch {
+        #[cfg(target_os = "linux")]
+        if let Err(e) = watch::run(&args, &filter, &mut actions, &mut index) {
+            eprintln!("{:?} watching directories, giving up", e);
+        }
+        #[cfg(not(target_os = "linux"))]
+        eprintln!("watch mode is only supported on Linux");
     }
-    let scan_duration = start.elapsed();
+}
+
+/// scan all directories, find duplicates among the files and act on them
+fn run(args: &Args, filter: &Filter, actions: &mut Actions) -> (Index, Stats) {
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    let Index {
+        all_dirs,
+        mut files,
+        mut full_hash_cache,
+    } = load_index(args).unwrap_or_else(|| scan(args, filter));
+    stats.scan_time = start.elapsed();
     let sort_start = Instant::now();
     files.sort_unstable_by_key(|file| file.size);
-    let sort_duration = sort_start.elapsed();
+    stats.sort_time = sort_start.elapsed();
     if args.print_files {
         for file in &files {
-            println!("{:?}", file);
+            if args.print0 {
+                output::print0(&file_name(&all_dirs[file.dir_index], &file.name));
+            } else {
+                println!("{:?}", file);
+            }
             //      println!("{} {} {} {}</>{}",file.id,file.nlink,file.size,all_dirs[file.dir_index].to_str().unwrap(), file.name);
         }
     }
     if args.print_directories {
         for dir in &all_dirs {
-            println!("{:?}", dir);
+            if args.print0 {
+                output::print0(dir);
+            } else {
+                println!("{:?}", dir);
+            }
         }
     }
     if args.timings {
-        println!("Scanning of directories took {:?}", scan_duration);
-        println!("Sorting of files took {:?}", sort_duration);
+        stats.print_scan();
     }
     let mut total_size = 0;
     files.iter().for_each(|f| total_size += f.size);
-    println!(
-        "total {} files, {} directories, {} data",
-        files.len(),
-        all_dirs.len(),
-        kmgt(total_size)
-    );
-    let mut cur = 0;
-    let len_1 = files.len() - 1;
-    let mut files_with_equals = 0;
-    let mut sets_with_equals = 0;
-    let mut fully_linked = 0;
-    let mut old_link_save = 0;
-    let mut set_of_2 = 0;
-    let mut linked = 0;
-    let mut new_link_save = 0;
-    let mut file_compares = 0;
-    let mut compare_time = Duration::new(0, 0);
-    let mut peek_hash_time = Duration::new(0, 0);
-    let mut full_hash_time = Duration::new(0, 0);
-    #[cfg(windows)]
-    let mut link_test_time = Duration::new(0, 0);
-    let mut peek_hashes = 0;
-    let mut full_hashes = 0;
-    let mut full_hash_size = 0;
-    let mut set_merges = 0;
-    let mut merged_files = 0;
-    let mut processed_size = 0;
-    let mut last_size_percent = 0;
-    let mut last_file_percent = 0;
+    stats.total_files = files.len();
+    stats.total_dirs = all_dirs.len();
+    stats.total_size = total_size;
+    // NUL separated and fdupes output have to stay machine readable
+    let chatty = !args.quiet && !args.print0 && !args.fdupes && !args.summarize;
+    if chatty {
+        println!(
+            "total {} files, {} directories, {} data",
+            files.len(),
+            all_dirs.len(),
+            kmgt(total_size)
+        );
+    }
+    // files below reference roots are never replaced and are the preferred link sources
+    let roots = Roots::new(args);
+    let writable: Vec<bool> = all_dirs.iter().map(|dir| roots.writable(dir)).collect();
+    let preferred: Vec<bool> = all_dirs.iter().map(|dir| roots.preferred(dir)).collect();
+    // hashes loaded from an index may be outdated, they are trusted for reports but not for linking
+    if args.link_duplicates {
+        full_hash_cache.clear();
+    }
+    // listings from the scan cache or an index may be outdated, candidates are checked again
+    let recheck = args.scan_cache.is_some() || args.index.is_some();
+    // ranges of files with the same size, only they can be duplicates
+    let mut groups: Vec<std::ops::Range<usize>> = Vec::new();
+    let mut group_start = 0;
+    for same in files.chunk_by(|a, b| a.size == b.size) {
+        if same.len() > 1 {
+            groups.push(group_start..group_start + same.len());
+        }
+        group_start += same.len();
+    }
+    // files without another one of the same size need no work
+    let mut done_files = files.len() - groups.iter().map(|group| group.len()).sum::<usize>();
+    let mut processed_size = total_size
+        - groups
+            .iter()
+            .map(|group| (group.len() as u64) * files[group.start].size)
+            .sum::<u64>();
+    // spinning disks read the groups in the order of their files on disk instead of by size,
+    // so that the disk head sweeps over the disk once instead of seeking back and forth
+    if args.hdd {
+        groups.sort_by_cached_key(|group| {
+            group
+                .clone()
+                .map(|i| disk_position(&all_dirs[files[i].dir_index], &files[i].name))
+                .min()
+        });
+    }
+    progress::start_grouping(files.len(), total_size);
     macro_rules! process_duplicate {
-        ($dir : expr, $file : expr, $index : expr) => {
-            if let Some(ref mut f) = csv_file {
-                writeln!(
-                    f,
-                    "\"{}/{}\",{},\"{}/{}\"",
-                    $dir.display(),
-                    $file,
-                    &files[$index].size,
-                    all_dirs.get(files[$index].dir_index).unwrap().display(),
-                    &files[$index].name,
-                )
-                .expect("csv write");
-            }
-            if args.report_duplicates {
-                println!(
-                    "\"{}/{}\" => \"{}/{}\"",
-                    $dir.display(),
-                    $file,
-                    all_dirs.get(files[$index].dir_index).unwrap().display(),
-                    &files[$index].name,
-                );
-            }
-            if args.link_duplicates {
-                link(
-                    $dir,
-                    $file,
-                    all_dirs.get(files[$index].dir_index).unwrap(),
-                    &files[$index].name,
-                );
+        ($dir : expr, $file : expr, $index : expr, $merged : expr) => {
+            actions.duplicate(
+                $dir,
+                $file,
+                all_dirs.get(files[$index].dir_index).unwrap(),
+                &files[$index].name,
+                files[$index].size,
+                $merged,
+            );
+        };
+    }
+    // file systems are checked once per directory, most can't have reflinks at all
+    let mut reflink_dirs: Vec<Option<bool>> = vec![None; all_dirs.len()];
+    macro_rules! shared_extents {
+        ($index : expr) => {{
+            let dir_index = files[$index].dir_index;
+            let dir = &all_dirs[dir_index];
+            if *reflink_dirs[dir_index].get_or_insert_with(|| reflinks_possible(dir)) {
+                shared_extents(dir, &files[$index].name)
+            } else {
+                None
             }
+        }};
+    }
+    // some file of the run is a preferred link source
+    macro_rules! preferred_run {
+        ($start : expr, $len : expr) => {
+            ($start..$start + $len).any(|i| preferred[files[i].dir_index])
         };
     }
-    // merge two runs of hard-linked files, all files of $merge_run are linked to $ref_run_start
-    macro_rules! merge_runs {
-        ($ref_run_start : expr, $merge_run_start : expr, $len : expr) => {
-            assert!(files[$ref_run_start].size == files[$merge_run_start].size);
-            merged_files += $len;
-            set_merges += 1;
-            new_link_save += files[$ref_run_start].size;
-            let dir = all_dirs.get(files[$ref_run_start].dir_index).unwrap();
-            let file = &files[$ref_run_start].name;
-            #[cfg(debug_assertions)]
-            println!(
-                "merging runs at {:?}/{file}[{}] and {}[{}]",
-                dir, $ref_run_start, $merge_run_start, $len
-            );
-            if args.link_duplicates {
-                for i in $merge_run_start..$merge_run_start + $len {
-                    process_duplicate!(&dir, &file, i);
+    // full hash of a file, hashes computed earlier are reused
+    macro_rules! cached_full_hash {
+        ($index : expr) => {{
+            let key = (files[$index].id, files[$index].size);
+            match full_hash_cache.get(&key) {
+                Some(hash) => Ok(*hash),
+                None => {
+                    let full_hash_start = Instant::now();
+                    stats.full_hash_size += files[$index].size;
+                    stats.full_hashes += 1;
+                    let hash = full_hash(
+                        all_dirs.get(files[$index].dir_index).unwrap(),
+                        &files[$index].name,
+                    );
+                    stats.full_hash_time += full_hash_start.elapsed();
+                    if let Ok(hash) = hash {
+                        full_hash_cache.insert(key, hash);
+                    }
+                    hash
                 }
             }
-            linked += $len;
-        };
+        }};
     }
-    // compare 2 files, hard link them if they match
-    macro_rules! file_cmp_link {
-        ($file1_i : expr, $file2_i : expr) => {
-            assert!(files[$file1_i].size == files[$file2_i].size);
+    // byte-compare two files
+    macro_rules! compare_files {
+        ($file1_i : expr, $file2_i : expr) => {{
             let compare_start = Instant::now();
-            if file_cmp(
+            let equal = file_cmp(
                 all_dirs.get(files[$file1_i].dir_index).unwrap(),
                 &files[$file1_i].name,
                 all_dirs.get(files[$file2_i].dir_index).unwrap(),
                 &files[$file2_i].name,
-            ) {
-                process_duplicate!(
-                    all_dirs.get(files[$file1_i].dir_index).unwrap(),
-                    &files[$file1_i].name,
-                    $file2_i
+            );
+            stats.compare_time += compare_start.elapsed();
+            stats.file_compares += 1;
+            equal
+        }};
+    }
+    // two files have the same content, with --trust-hash equal full hashes suffice
+    macro_rules! same_content {
+        ($file1_i : expr, $file2_i : expr) => {{
+            if args.trust_hash {
+                match (cached_full_hash!($file1_i), cached_full_hash!($file2_i)) {
+                    (Ok(hash1), Ok(hash2)) => hash1 == hash2,
+                    _ => false,
+                }
+            } else {
+                compare_files!($file1_i, $file2_i)
+            }
+        }};
+    }
+    // merge two runs of hard-linked files, all files of the merge run are linked to the ref run
+    // unless only the merge run contains preferred files, then the roles are swapped
+    macro_rules! merge_runs {
+        ($ref_run_start : expr, $ref_len : expr, $merge_run_start : expr, $merge_len : expr) => {{
+            assert!(files[$ref_run_start].size == files[$merge_run_start].size);
+            let (ref_run_start, ref_len, merge_run_start, len) =
+                if preferred_run!($merge_run_start, $merge_len)
+                    && !preferred_run!($ref_run_start, $ref_len)
+                {
+                    ($merge_run_start, $merge_len, $ref_run_start, $ref_len)
+                } else {
+                    ($ref_run_start, $ref_len, $merge_run_start, $merge_len)
+                };
+            // with reference roots, only duplicates of reference files are of interest
+            if !roots.references || preferred_run!(ref_run_start, ref_len) {
+                let dir = all_dirs.get(files[ref_run_start].dir_index).unwrap();
+                let file = &files[ref_run_start].name;
+                #[cfg(debug_assertions)]
+                eprintln!(
+                    "merging runs at {:?}/{file:?}[{}] and {}[{}]",
+                    dir, ref_run_start, merge_run_start, len
                 );
-                linked += 1;
-                new_link_save += files[$file1_i].size;
+                let mut merged = 0;
+                for i in merge_run_start..merge_run_start + len {
+                    if writable[files[i].dir_index] {
+                        process_duplicate!(&dir, &file, i, true);
+                        merged += 1;
+                    }
+                }
+                if merged > 0 {
+                    stats.merged_files += merged;
+                    stats.set_merges += 1;
+                    stats.linked += merged;
+                }
+                // space is only freed when no name of the merged run is left
+                if merged == len {
+                    stats.new_link_save += files[ref_run_start].size;
+                }
             }
-            compare_time += compare_start.elapsed();
-            file_compares += 1;
-        };
+        }};
     }
-    while cur < len_1 {
-        // TODO: improve progress reporting, search on crates.io
-        let file_percent = 100 * cur / len_1;
-        let size_percent = 100 * processed_size / total_size;
-        if file_percent != last_file_percent || size_percent != last_size_percent {
-            print!(
-                "progress: current size {}, {file_percent}% ({cur}/{len_1}) files, {size_percent}% ({}/{}) data    \r",
-				kmgt(files[cur].size),
-                kmgt(processed_size),
-                kmgt(total_size)
-            );
-            let _ = std::io::stdout().flush();
-            last_file_percent = file_percent;
-            last_size_percent = size_percent;
-        }
-        if files[cur].size != files[cur + 1].size {
-            processed_size += files[cur].size;
-            cur += 1;
-            continue;
-        }
-        sets_with_equals += 1;
+    // compare 2 files, hard link them if they match
+    macro_rules! file_cmp_link {
+        ($file1_i : expr, $file2_i : expr) => {{
+            assert!(files[$file1_i].size == files[$file2_i].size);
+            let (file1_i, file2_i) = if preferred[files[$file2_i].dir_index] {
+                ($file2_i, $file1_i)
+            } else {
+                ($file1_i, $file2_i)
+            };
+            if writable[files[file2_i].dir_index]
+                && (!roots.references || preferred[files[file1_i].dir_index])
+            {
+                if same_content!(file1_i, file2_i) {
+                    process_duplicate!(
+                        all_dirs.get(files[file1_i].dir_index).unwrap(),
+                        &files[file1_i].name,
+                        file2_i,
+                        false
+                    );
+                    stats.linked += 1;
+                    stats.new_link_save += files[file1_i].size;
+                }
+            }
+        }};
+    }
+    for group in groups {
+        progress::position(done_files, processed_size);
+        done_files += group.len();
+        stats.sets_with_equals += 1;
         // candidate for duplicate
-        let ref_index = cur;
-        #[cfg(not(windows))]
-        {
-            cur += 2;
-        }
+        let mut ref_index = group.start;
+        let cur = group.end;
         #[cfg(windows)]
-        let link_test_start = Instant::now();
-        while cur <= len_1 && files[cur].size == files[ref_index].size {
+        {
+            let link_test_start = Instant::now();
             // we delayed getting the Windows file if (FindFileFirst) until now as this requires
             // disk access and can be avoided for all files that have no other file(s) with the
             // same length.
-            #[cfg(windows)]
-            {
-                files[cur].id = windows_id(
-                    all_dirs.get(files[cur].dir_index).unwrap(),
-                    &files[cur].name,
-                );
+            for i in group {
+                files[i].id = windows_id(all_dirs.get(files[i].dir_index).unwrap(), &files[i].name);
             }
-            cur += 1;
+            stats.link_test_time += link_test_start.elapsed();
         }
-        #[cfg(windows)]
-        {
-            link_test_time += link_test_start.elapsed();
+        if recheck {
+            // move changed files to the front and leave them out of the group
+            let start = ref_index;
+            for i in start..cur {
+                if !still_candidate(&all_dirs[files[i].dir_index], &files[i], filter) {
+                    files.swap(ref_index, i);
+                    processed_size += files[ref_index].size;
+                    stats.changed_files += 1;
+                    ref_index += 1;
+                }
+            }
+            if cur - ref_index < 2 {
+                processed_size += ((cur - ref_index) as u64) * files[cur - 1].size;
+                continue;
+            }
         }
-        files_with_equals += cur - ref_index;
+        stats.files_with_equals += cur - ref_index;
         processed_size += ((cur - ref_index) as u64) * files[ref_index].size;
         #[cfg(debug_assertions)]
-        println!("{ref_index}..{cur}@{:}", files[ref_index].size);
+        eprintln!("{ref_index}..{cur}@{:}", files[ref_index].size);
         // now files[ref .. cur-1] have the same size and their id (inode or first file name) is known
         // sort that range by id (inode)
         files
@@ -306,18 +706,33 @@ fn main() {
             .unwrap()
             .sort_unstable_by_key(|f| f.id);
         if files[ref_index].id == files[cur - 1].id {
-            fully_linked += 1;
-            old_link_save += ((cur - ref_index - 1) as u64) * files[ref_index].size;
+            stats.fully_linked += 1;
+            stats.old_link_save += ((cur - ref_index - 1) as u64) * files[ref_index].size;
             #[cfg(debug_assertions)]
-            println!("run {ref_index}..{cur} is fully linked");
+            eprintln!("run {ref_index}..{cur} is fully linked");
+            continue;
+        }
+        // with reference roots, only groups with reference files and files to replace are of interest
+        if roots.references
+            && !(preferred_run!(ref_index, cur - ref_index)
+                && (ref_index..cur).any(|i| writable[files[i].dir_index]))
+        {
             continue;
         }
         if cur - ref_index == 2 {
             #[cfg(debug_assertions)]
-            println!("set of 2");
+            eprintln!("set of 2");
             // just 2 files
             // direct compare
-            set_of_2 += 1;
+            stats.set_of_2 += 1;
+            // reflink clones share their data already
+            let extents = shared_extents!(ref_index);
+            if extents.is_some() && extents == shared_extents!(cur - 1) {
+                stats.already_shared += 1;
+                stats.old_share_save += files[ref_index].size;
+                stats.fully_shared += 1;
+                continue;
+            }
             file_cmp_link!(cur - 1, ref_index);
             continue;
         }
@@ -346,38 +761,60 @@ fn main() {
             len: cur - run_start,
             peek_hash: 0,
         });
+        // runs of reflink clones share all extents, like hard links they need no further work,
+        // only the preferred or longest run of them is kept in the group
+        runs.sort_by_key(|r| std::cmp::Reverse((preferred_run!(r.first, r.len), r.len)));
+        let extents: Vec<_> = runs.iter().map(|r| shared_extents!(r.first)).collect();
+        if extents.iter().flatten().count() > 1 {
+            let mut keep = vec![true; runs.len()];
+            for j in 0..runs.len() {
+                if extents[j].is_none() || !keep[j] {
+                    continue;
+                }
+                for k in j + 1..runs.len() {
+                    if keep[k] && extents[k] == extents[j] {
+                        keep[k] = false;
+                        stats.already_shared += runs[k].len;
+                        stats.old_share_save += files[runs[k].first].size;
+                    }
+                }
+            }
+            let mut keep = keep.into_iter();
+            runs.retain(|_| keep.next().unwrap());
+            if runs.len() == 1 {
+                stats.fully_shared += 1;
+                continue;
+            }
+        }
         if runs.len() == 2 {
             #[cfg(debug_assertions)]
-            println!("2 runs of same inode");
-            let compare_start = Instant::now();
-            if file_cmp(
-                all_dirs.get(files[runs[0].first].dir_index).unwrap(),
-                &files[runs[0].first].name,
-                all_dirs.get(files[runs[1].first].dir_index).unwrap(),
-                &files[runs[1].first].name,
-            ) {
+            eprintln!("2 runs of same inode");
+            if same_content!(runs[0].first, runs[1].first) {
                 #[cfg(debug_assertions)]
-                println!(
+                eprintln!(
                     "merging pair runs with same {:?} and {:?}",
                     runs[0], runs[1]
                 );
                 if runs[0].len > runs[1].len {
-                    merge_runs!(runs[0].first, runs[1].first, runs[1].len);
+                    merge_runs!(runs[0].first, runs[0].len, runs[1].first, runs[1].len);
                 } else {
-                    merge_runs!(runs[1].first, runs[0].first, runs[0].len);
+                    merge_runs!(runs[1].first, runs[1].len, runs[0].first, runs[0].len);
                 }
             }
-            compare_time += compare_start.elapsed();
-            file_compares += 1;
             continue;
         }
         #[cfg(debug_assertions)]
-        println!("computing peek hashes");
+        eprintln!("computing peek hashes");
         // peek hash first
-        peek_hashes += runs.len();
+        stats.peek_hashes += runs.len();
         let hash_start = Instant::now();
+        if args.hdd {
+            runs.sort_by_cached_key(|r| {
+                disk_position(&all_dirs[files[r.first].dir_index], &files[r.first].name)
+            });
+        }
         runs.iter_mut().for_each(|r| {
-            r.peek_hash = match peek_hash(
+            r.peek_hash = peek_hash(
                 all_dirs.get(files[r.first].dir_index).unwrap(),
                 &files[r.first].name,
                 if files[r.first].size > args.peek_hash {
@@ -385,13 +822,15 @@ fn main() {
                 } else {
                     files[r.first].size
                 },
-            ) {
-                Ok(hash) => hash,
-                _ => 0,
-            }
+            )
+            .unwrap_or_default()
         });
-        runs.sort_unstable_by(|a, b| a.peek_hash.cmp(&b.peek_hash));
-        peek_hash_time += hash_start.elapsed();
+        runs.sort_unstable_by_key(|a| a.peek_hash);
+        stats.peek_hash_time += hash_start.elapsed();
+        stats.peek_eliminated += runs
+            .chunk_by(|a, b| a.peek_hash == b.peek_hash)
+            .filter(|same| same.len() == 1 && same[0].peek_hash != 0)
+            .count();
         // identify runs of same peek_hash
         let len_1 = runs.len() - 1;
         let mut i = 0;
@@ -400,213 +839,646 @@ fn main() {
             i += 1;
         }
         #[cfg(debug_assertions)]
-        println!("grouping runs by peek {len_1}");
+        eprintln!("grouping runs by peek {len_1}");
         while i < len_1 {
             if runs[i].peek_hash == runs[i + 1].peek_hash {
                 if i + 1 == len_1 || runs[i].peek_hash != runs[i + 2].peek_hash {
                     // just 2 runs with the same peek_hash -> direct compare
                     let f_ref = runs[i].first;
-                    let compare_start = Instant::now();
-                    if file_cmp(
-                        all_dirs.get(files[f_ref].dir_index).unwrap(),
-                        &files[f_ref].name,
-                        all_dirs.get(files[runs[i + 1].first].dir_index).unwrap(),
-                        &files[runs[i + 1].first].name,
-                    ) {
+                    if same_content!(f_ref, runs[i + 1].first) {
                         // comparison function ensured that the first run is the longest
-                        merge_runs!(f_ref, runs[i + 1].first, runs[i + 1].len);
+                        merge_runs!(f_ref, runs[i].len, runs[i + 1].first, runs[i + 1].len);
                     }
-                    compare_time += compare_start.elapsed();
                     i += 2;
                     continue;
                 }
                 // we have a sequence of three or more runs with the same
-                // peek_hash - try to distinguish them by full hashing algorithm
-                #[derive(Debug)]
-                struct RunRun {
-                    first: usize,
-                    len: usize,
-                    hash: FullHash,
-                }
-                let mut run_runs = Vec::<RunRun>::new();
+                // peek_hash - try to distinguish them by samples and the full hashing algorithm
                 let ref_hash = runs[i].peek_hash;
-                let full_hash_start = Instant::now();
+                let mut candidates = Vec::new();
                 while i <= len_1 && runs[i].peek_hash == ref_hash {
-                    full_hash_size += files[runs[i].first].size;
-                    full_hashes += 1;
-                    match full_hash(
-                        all_dirs.get(files[runs[i].first].dir_index).unwrap(),
-                        &files[runs[i].first].name,
-                    ) {
-                        Ok(hash) => run_runs.push(RunRun {
-                            first: runs[i].first,
-                            len: runs[i].len,
-                            hash,
-                        }),
-                        _ => (),
-                    }
-
+                    candidates.push((runs[i].first, runs[i].len));
                     i += 1;
                 }
-                full_hash_time += full_hash_start.elapsed();
-                if run_runs.len() > 1 {
-                    // need stable sort here to keep longest run first
-                    run_runs.sort_by_key(|r| r.hash);
-                    // last sprint: check for run_runs with same hash
-                    // these files have same size, same peek_hash and same full hash
-                    // let's merge them
-                    //println!("{:?}", run_runs);
-                    let mut i = 1;
-                    let mut run_index = 0;
-                    while i < run_runs.len() {
-                        while i < run_runs.len() && run_runs[i].hash == run_runs[run_index].hash {
-                            merge_runs!(run_runs[run_index].first, run_runs[i].first, run_runs[i].len);
-                            i += 1;
+                for mut candidates in sample_groups(args, &all_dirs, &files, candidates, &mut stats)
+                {
+                    if args.hdd {
+                        candidates.sort_by_cached_key(|&(first, _)| {
+                            disk_position(&all_dirs[files[first].dir_index], &files[first].name)
+                        });
+                    }
+                    if candidates.len() == 2 {
+                        let ((first0, len0), (first1, len1)) = (candidates[0], candidates[1]);
+                        if same_content!(first0, first1) {
+                            if len0 >= len1 {
+                                merge_runs!(first0, len0, first1, len1);
+                            } else {
+                                merge_runs!(first1, len1, first0, len0);
+                            }
+                        }
+                        continue;
+                    }
+                    // small groups are compared in lockstep, which stops reading files as soon as they
+                    // differ, unless all hashes are known already
+                    let streamed = !args.trust_hash
+                        && candidates.len() <= STREAM_GROUP_MAX
+                        && !candidates.iter().all(|&(first, _)| {
+                            full_hash_cache.contains_key(&(files[first].id, files[first].size))
+                        });
+                    let classes: Vec<Vec<(usize, usize)>> = if streamed {
+                        let compare_start = Instant::now();
+                        let paths: Vec<PathBuf> = candidates
+                            .iter()
+                            .map(|&(first, _)| {
+                                file_name(&all_dirs[files[first].dir_index], &files[first].name)
+                            })
+                            .collect();
+                        let classes = stream_cmp(&paths)
+                            .into_iter()
+                            .map(|class| class.into_iter().map(|k| candidates[k]).collect())
+                            .collect();
+                        stats.compare_time += compare_start.elapsed();
+                        stats.stream_compares += 1;
+                        classes
+                    } else {
+                        let mut hashed = Vec::new();
+                        for (first, len) in candidates {
+                            if let Ok(hash) = cached_full_hash!(first) {
+                                hashed.push((hash, (first, len)));
+                            }
+                        }
+                        // need stable sort here to keep longest run first
+                        hashed.sort_by_key(|(hash, _)| *hash);
+                        // these files have same size, same peek_hash and same full hash
+                        hashed
+                            .chunk_by(|a, b| a.0 == b.0)
+                            .filter(|same| same.len() > 1)
+                            .map(|same| same.iter().map(|(_, run)| *run).collect())
+                            .collect()
+                    };
+                    // last sprint: merge the runs with the same content
+                    for mut class in classes {
+                        // files that must not be replaced are the link source of all others,
+                        // otherwise the longest run is kept so that the fewest files are replaced
+                        let j = (0..class.len())
+                            .find(|&j| preferred_run!(class[j].0, class[j].1))
+                            .or_else(|| (0..class.len()).max_by_key(|&j| class[j].1))
+                            .unwrap_or(0);
+                        class.swap(0, j);
+                        let (ref_first, ref_len) = class[0];
+                        for &(first, len) in &class[1..] {
+                            // with --paranoid equal hashes are not trusted
+                            if args.paranoid && !streamed && !compare_files!(ref_first, first) {
+                                stats.hash_collisions += 1;
+                                continue;
+                            }
+                            merge_runs!(ref_first, ref_len, first, len);
                         }
-                        run_index = i;
-                        i += 1;
                     }
                 }
+                continue;
             }
             i += 1;
         }
     }
+    progress::finish();
     // skip progress report line
-    println!(
-        "progress: {} files, {} data                                                 ",
-        cur + 1,
-        kmgt(total_size)
-    );
-    if args.timings {
-        #[cfg(windows)]
-        println!("spent {:?} to get unique file ids", link_test_time);
-        println!("{files_with_equals} files in {sets_with_equals} sets of equal size grouped");
+    if chatty {
         println!(
-            "{fully_linked} sets were already linked, saving {}",
-            kmgt(old_link_save)
+            "progress: {} files, {} data                                                 ",
+            files.len(),
+            kmgt(total_size)
         );
-        if args.link_duplicates {
-            println!(
-                "{set_of_2} pairs compared, created {linked} new links saving {}",
-                kmgt(new_link_save)
-            );
-        } else {
-            println!(
-                "{set_of_2} pairs compared, linking would save {}",
-                kmgt(new_link_save)
-            );
+    }
+    let sets = actions.sets.as_deref().unwrap_or_default();
+    if args.print0 {
+        sets.iter().for_each(output::print0_set);
+    }
+    if args.summarize {
+        output::print_summary(sets);
+    } else if args.fdupes {
+        output::print_fdupes(sets, args.size);
+    }
+    stats.shrunk_files = reader::shrunk();
+    stats.total_time = start.elapsed();
+    if args.timings {
+        stats.print(args.link_duplicates);
+    }
+    if let Some(format) = args.stats_format {
+        if let Err(e) = stats.export(format, args.stats_file.as_deref()) {
+            eprintln!("{:?} writing statistics", e);
         }
+    }
+    let index = Index {
+        all_dirs,
+        files,
+        full_hash_cache,
+    };
+    if let Some(ref path) = args.save_index {
+        if let Err(e) = index_file::save(&index, path) {
+            eprintln!("{:?} writing index {}", e, path.display());
+        }
+    }
+    if let Some(ref path) = args.sqlite {
+        let sets = actions.sets.as_deref().unwrap_or_default();
+        if let Err(e) = sqlite_export::export(path, &index, sets) {
+            eprintln!("{:?} writing SQLite database {}", e, path.display());
+        }
+    }
+    (index, stats)
+}
+
+/// list and sort the files and save them, the contents are only read by later runs on the index
+fn scan_only(args: &Args, filter: &Filter) {
+    let start = Instant::now();
+    let mut index = load_index(args).unwrap_or_else(|| scan(args, filter));
+    index.files.sort_unstable_by_key(|file| file.size);
+    if !args.quiet && !args.print0 && !args.fdupes && !args.summarize {
         println!(
-            "spent {:?} comparing {file_compares} file pairs",
-            compare_time
-        );
-        println!(
-            "spent {:?} computing {peek_hashes} peek hashes",
-            peek_hash_time
-        );
-        println!(
-            "spent {:?} computing {full_hashes} full hashes, ({})",
-            full_hash_time,
-            kmgt(full_hash_size)
+            "total {} files, {} directories, {} data",
+            index.files.len(),
+            index.all_dirs.len(),
+            kmgt(index.files.iter().map(|file| file.size).sum())
         );
-        if merged_files > 0 {
-            println!("merged {merged_files} files into {set_merges} existing sets");
+    }
+    if args.timings {
+        textln!("Scanning and sorting took {:?}", start.elapsed());
+    }
+    if let Some(ref path) = args.save_index {
+        if let Err(e) = index_file::save(&index, path) {
+            eprintln!("{:?} writing index {}", e, path.display());
         }
-        println!("Total time spent {:?}", start.elapsed());
     }
 }
 
-/// nicely format number of bytes into human-readable form
-fn kmgt(bytes: u64) -> String {
-    if bytes < 1024 {
-        return format!("{bytes} B");
-    }
-    if bytes < 1024 * 1024 {
-        let mag = 1024;
-        let f = (bytes % mag) * 10 / mag;
-        return format!("{}.{f} kiB", bytes / mag);
-    }
-    if bytes < 1024 * 1024 * 1024 {
-        let mag = 1024 * 1024;
-        let f = (bytes % mag) * 10 / mag;
-        return format!("{}.{f} MiB", bytes / mag);
-    }
-    if bytes < 1024 * 1024 * 1024 * 1024 {
-        let mag = 1024 * 1024 * 1024;
-        let f = (bytes % mag) * 10 / mag;
-        return format!("{}.{f} GiB", bytes / mag);
-    }
-    let mag = 1024 * 1024 * 1024 * 1024;
-    let f = (bytes % mag) * 10 / mag;
-    format!("{}.{f} TiB", bytes / mag)
+/// index file given with --index, None if there is none or it could not be loaded
+fn load_index(args: &Args) -> Option<Index> {
+    let path = args.index.as_deref()?;
+    match index_file::load(path) {
+        Ok(index) => Some(index),
+        Err(e) => {
+            eprintln!(
+                "{:?} loading index {}, scanning directories",
+                e,
+                path.display()
+            );
+            None
+        }
+    }
 }
 
-// type FullHash has to match digest used in full_hash()
-// and has to implement Ord, PartialOrd, and Eq for sorting
-type FullHash = [u8; 32];
+/// find all files below the directories given in args
+fn scan(args: &Args, filter: &Filter) -> Index {
+    let mut files: Vec<FileInfo> = Vec::new();
+    let mut all_dirs: Vec<PathBuf> = vec![];
 
-/// compute full hash of file
-fn full_hash(dir: &PathBuf, name: &str) -> Result<FullHash, std::io::Error> {
-    let mut hasher = blake3::Hasher::new();
-    hasher.update_mmap(file_name(dir, name))?;
-    Ok(*hasher.finalize().as_bytes())
+    let mut ignores = IgnoreStack::new(&args.ignore_file);
+    let mut cache = args.scan_cache.as_deref().map(ScanCache::load);
+    for dir in args.reference.iter().chain(&args.directories) {
+        find_files(
+            dir,
+            &mut all_dirs,
+            &mut files,
+            filter,
+            &mut ignores,
+            cache.as_mut(),
+        );
+    }
+    progress::finish();
+    if let (Some(cache), Some(path)) = (&cache, &args.scan_cache) {
+        if let Err(e) = cache.save(path) {
+            eprintln!("{:?} writing scan cache {}", e, path.display());
+        }
+        if args.timings {
+            textln!(
+                "reused {} of {} directories from the scan cache",
+                cache.reused,
+                cache.reused + cache.read
+            );
+        }
+    }
+    Index {
+        all_dirs,
+        files,
+        full_hash_cache: HashCache::new(),
+    }
 }
 
-// type PeekHash has to match digest used in peek_hash()
-// and has to implement Ord, PartialOrd, and Eq for sorting
-type PeekHash = u128;
+// bytes hashed between two progress updates
+const HASH_CHUNK: usize = 16 << 20;
 
-fn file_name(dir: &PathBuf, name: &str) -> PathBuf {
-    let mut file_name = dir.clone();
+/// compute full hash of file
+fn full_hash(dir: &Path, name: &OsStr) -> Result<FullHash, std::io::Error> {
+    let path = file_name(dir, name);
+    progress::activity(Phase::FullHash, &path);
+    let mut hasher = FullHasher::new();
+    let mut reader = Reader::open(&path)?;
+    // hash in chunks to report progress on huge files
+    let chunk_len = reader.chunk_len(HASH_CHUNK);
+    let mut offset = 0;
+    loop {
+        let chunk = reader.read_at(offset, chunk_len)?;
+        if chunk.is_empty() {
+            break;
+        }
+        hasher.update(chunk);
+        progress::read(chunk.len() as u64);
+        offset += chunk.len() as u64;
+    }
+    reader.check()?;
+    Ok(hasher.finalize())
+}
+
+fn file_name(dir: &Path, name: &OsStr) -> PathBuf {
+    let mut file_name = dir.to_path_buf();
     file_name.push(name);
     file_name
 }
 
 /// compute hash of the first size bytes of file
-fn peek_hash(dir: &PathBuf, name: &str, size: u64) -> Result<PeekHash, std::io::Error> {
-    // fast murmur3 crate does not implement digest, hence we use memory mapping to provide continuous access
-    let file = File::open(file_name(dir, name))?;
-    let buffer = unsafe { Mmap::map(&file)? };
-    Ok(fastmurmur3::hash(&buffer[0..(size as usize)]))
+fn peek_hash(dir: &Path, name: &OsStr, size: u64) -> Result<PeekHash, std::io::Error> {
+    let path = file_name(dir, name);
+    progress::activity(Phase::PeekHash, &path);
+    let mut reader = Reader::open(&path)?;
+    let head = reader.read_at(0, size as usize)?;
+    progress::read(head.len() as u64);
+    let hash = hash::peek(head);
+    reader.check()?;
+    Ok(hash)
+}
+
+/// position of the file on disk for reading files in disk order with --hdd, unknown positions go last
+#[cfg(target_os = "linux")]
+fn disk_position(dir: &Path, name: &OsStr) -> u64 {
+    File::open(file_name(dir, name))
+        .ok()
+        .and_then(|file| fiemap::first_physical(&file))
+        .unwrap_or(u64::MAX)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disk_position(_dir: &Path, _name: &OsStr) -> u64 {
+    u64::MAX
+}
+
+/// file system of dir supports reflinks, elsewhere looking for shared extents is wasted work
+#[cfg(target_os = "linux")]
+fn reflinks_possible(dir: &Path) -> bool {
+    fiemap::may_share_extents(dir)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflinks_possible(_dir: &Path) -> bool {
+    false
+}
+
+/// physical extents of a file whose data is completely shared with other files,
+/// files with the same shared extents are reflink clones of each other
+#[cfg(target_os = "linux")]
+fn shared_extents(dir: &Path, name: &OsStr) -> Option<Vec<(u64, u64, u64)>> {
+    let file = File::open(file_name(dir, name)).ok()?;
+    let extents = fiemap::extents(&file).ok()?;
+    let shared = !extents.is_empty()
+        && extents.iter().all(|extent| {
+            extent.flags & fiemap::FIEMAP_EXTENT_SHARED != 0
+                && extent.flags & fiemap::FIEMAP_EXTENT_UNKNOWN == 0
+        });
+    shared.then(|| {
+        extents
+            .iter()
+            .map(|extent| (extent.logical, extent.physical, extent.length))
+            .collect()
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn shared_extents(_dir: &Path, _name: &OsStr) -> Option<Vec<(u64, u64, u64)>> {
+    None
+}
+
+// number of segments hashed by the spread sample stage
+const SPREAD_SAMPLES: u64 = 8;
+
+/// compute hash of the segments of file selected by stage, each block bytes long
+fn sample_hash(
+    dir: &Path,
+    name: &OsStr,
+    size: u64,
+    block: u64,
+    stage: SampleStage,
+) -> Result<PeekHash, std::io::Error> {
+    let path = file_name(dir, name);
+    progress::activity(Phase::Sample, &path);
+    let mut reader = Reader::open(&path)?;
+    let last = size - block;
+    let offsets: Vec<u64> = match stage {
+        SampleStage::Tail => vec![last],
+        SampleStage::Middle => vec![last / 2],
+        SampleStage::Spread => (1..=SPREAD_SAMPLES)
+            .map(|k| last / (SPREAD_SAMPLES + 1) * k)
+            .collect(),
+    };
+    let mut samples = Vec::with_capacity(offsets.len() * block as usize);
+    for offset in offsets {
+        samples.extend_from_slice(reader.read_at(offset, block as usize)?);
+    }
+    reader.check()?;
+    progress::read(samples.len() as u64);
+    Ok(hash::peek(&samples))
+}
+
+/// split runs (first file and length) of equal size and peek hash by the hashes of the sample stages
+/// runs whose samples differ from all others are no duplicates and dropped
+fn sample_groups(
+    args: &Args,
+    all_dirs: &[PathBuf],
+    files: &[FileInfo],
+    candidates: Vec<(usize, usize)>,
+    stats: &mut Stats,
+) -> Vec<Vec<(usize, usize)>> {
+    let size = files[candidates[0].0].size;
+    let mut groups = vec![candidates];
+    // the peek hash already covered small files completely
+    if size <= args.peek_hash {
+        return groups;
+    }
+    for &stage in &args.sample_stages {
+        let sample_start = Instant::now();
+        let mut split = Vec::new();
+        for mut group in groups {
+            // pairs are compared directly
+            if group.len() < 3 {
+                split.push(group);
+                continue;
+            }
+            if args.hdd {
+                group.sort_by_cached_key(|&(first, _)| {
+                    disk_position(&all_dirs[files[first].dir_index], &files[first].name)
+                });
+            }
+            let mut hashed = Vec::with_capacity(group.len());
+            for run in group {
+                let file = &files[run.0];
+                stats.sample_hashes += 1;
+                if let Ok(hash) = sample_hash(
+                    &all_dirs[file.dir_index],
+                    &file.name,
+                    size,
+                    args.peek_hash,
+                    stage,
+                ) {
+                    hashed.push((hash, run));
+                }
+            }
+            hashed.sort_by_key(|(hash, _)| *hash);
+            for same in hashed.chunk_by(|a, b| a.0 == b.0) {
+                if same.len() > 1 {
+                    split.push(same.iter().map(|(_, run)| *run).collect());
+                    continue;
+                }
+                match stage {
+                    SampleStage::Tail => stats.tail_eliminated += 1,
+                    SampleStage::Middle => stats.middle_eliminated += 1,
+                    SampleStage::Spread => stats.spread_eliminated += 1,
+                }
+            }
+        }
+        groups = split;
+        stats.sample_hash_time += sample_start.elapsed();
+    }
+    groups
+}
+
+// largest group of runs that is compared in lockstep instead of hashed
+const STREAM_GROUP_MAX: usize = 8;
+
+// bytes compared at once
+const COMPARE_CHUNK: usize = 65536;
+
+/// compare files of the same size chunk by chunk and split them into classes of equal content
+/// as soon as they differ, only classes of two or more files are returned as indexes into paths
+fn stream_cmp(paths: &[PathBuf]) -> Vec<Vec<usize>> {
+    let mut readers = Vec::with_capacity(paths.len());
+    // paths of the files that could be opened
+    let mut opened = Vec::with_capacity(paths.len());
+    for (k, path) in paths.iter().enumerate() {
+        if let Ok(reader) = Reader::open(path) {
+            readers.push(reader);
+            opened.push(k);
+        }
+    }
+    let chunk_len = readers
+        .iter()
+        .map(|reader| reader.chunk_len(COMPARE_CHUNK))
+        .max()
+        .unwrap_or(COMPARE_CHUNK);
+    // classes hold indexes into readers until the end
+    let mut classes: Vec<Vec<usize>> = vec![(0..readers.len()).collect()];
+    classes.retain(|same| same.len() > 1);
+    let mut done = Vec::new();
+    let mut offset = 0;
+    while !classes.is_empty() {
+        let mut split: Vec<Vec<usize>> = Vec::with_capacity(classes.len());
+        for same in classes {
+            progress::activity(Phase::Compare, &paths[opened[same[0]]]);
+            // the first chunk of each class of this round, files that vanished or shrank
+            // only match files with the same fate
+            let mut chunk_classes: Vec<(Vec<u8>, Vec<usize>)> = Vec::new();
+            for r in same {
+                let chunk = match readers[r].read_at(offset, chunk_len) {
+                    Ok(chunk) => chunk,
+                    Err(_) => continue,
+                };
+                progress::read(chunk.len() as u64);
+                match chunk_classes.iter_mut().find(|(first, _)| first == chunk) {
+                    Some((_, members)) => members.push(r),
+                    None => chunk_classes.push((chunk.to_vec(), vec![r])),
+                }
+            }
+            for (chunk, members) in chunk_classes {
+                if members.len() < 2 {
+                    continue;
+                }
+                // all files of the class ended at the same point
+                if chunk.is_empty() {
+                    done.push(members);
+                } else {
+                    split.push(members);
+                }
+            }
+        }
+        classes = split;
+        offset += chunk_len as u64;
+    }
+    // files that shrank while being compared are left out
+    let intact: Vec<bool> = readers
+        .iter()
+        .map(|reader| reader.check().is_ok())
+        .collect();
+    done.into_iter()
+        .map(|same| -> Vec<usize> {
+            same.into_iter()
+                .filter(|&r| intact[r])
+                .map(|r| opened[r])
+                .collect()
+        })
+        .filter(|same| same.len() > 1)
+        .collect()
+}
+
+/// everything that is done with a confirmed duplicate: CSV export, reporting and hard linking
+struct Actions {
+    csv_file: Option<File>,
+    report: bool,
+    link: bool,
+    // only collected when somebody asks for them later, i.e. in daemon mode or for SQLite export
+    sets: Option<Vec<DuplicateSet>>,
+}
+
+/// files with identical content, all are linked to the first one
+#[derive(Debug)]
+struct DuplicateSet {
+    size: u64,
+    files: Vec<PathBuf>,
+    // for each file whether it was successfully replaced by a link, never true for the first
+    linked: Vec<bool>,
+}
+
+impl Actions {
+    fn new(args: &Args) -> Actions {
+        let mut csv_file: Option<File> = if let Some(ref csv_path) = args.csv_export {
+            match File::create(csv_path) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    eprintln!("{:?} creating {:?}, CSV output is not written", e, csv_path);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(ref mut f) = csv_file {
+            writeln!(f, "File,Size,Duplicate").expect("csv write");
+        }
+        Actions {
+            csv_file,
+            report: args.report_duplicates,
+            link: args.link_duplicates,
+            sets: (args.sqlite.is_some() || args.print0 || args.fdupes || args.summarize)
+                .then(Vec::new),
+        }
+    }
+
+    /// name2 in dir2 is a duplicate of name1 in dir1 and gets replaced by a link to it
+    /// merged duplicates already have other links, they only go to CSV and report when linking
+    fn duplicate(
+        &mut self,
+        dir1: &Path,
+        name1: &OsStr,
+        dir2: &Path,
+        name2: &OsStr,
+        size: u64,
+        merged: bool,
+    ) {
+        let listed = self.link || !merged;
+        if let Some(f) = self.csv_file.as_mut().filter(|_| listed) {
+            writeln!(
+                f,
+                "\"{}/{}\",{},\"{}/{}\"",
+                dir1.display(),
+                name1.display(),
+                size,
+                dir2.display(),
+                name2.display(),
+            )
+            .expect("csv write");
+        }
+        if self.report && listed {
+            progress::finish();
+            textln!(
+                "\"{}/{}\" => \"{}/{}\"",
+                dir1.display(),
+                name1.display(),
+                dir2.display(),
+                name2.display(),
+            );
+        }
+        let linked = self.link && link(dir1, name1, dir2, name2);
+        if let Some(ref mut sets) = self.sets {
+            // duplicates of the same file are reported one after the other
+            let original = file_name(dir1, name1);
+            match sets.last_mut() {
+                Some(set) if set.files[0] == original => {
+                    set.files.push(file_name(dir2, name2));
+                    set.linked.push(linked);
+                }
+                _ => sets.push(DuplicateSet {
+                    size,
+                    files: vec![original, file_name(dir2, name2)],
+                    linked: vec![false, linked],
+                }),
+            }
+        }
+    }
 }
 
 /// link file1 to file2, replacing file2
 // to protect against weird permission errors on Windows we first try
 // to create a temporary file in the same directory and then replace the target with rename
-fn link(dir1: &PathBuf, name1: &str, dir2: &PathBuf, name2: &str) {
+fn link(dir1: &Path, name1: &OsStr, dir2: &Path, name2: &OsStr) -> bool {
     let file_name2 = file_name(dir2, name2);
-    let tmp_name = file_name(dir2, (name2.to_owned() + ".dbl").as_str());
-    if let Ok(_) = fs::hard_link(file_name(dir1, name1), &tmp_name) {
-        let _ = fs::rename(tmp_name, file_name2);
+    progress::activity(Phase::Link, &file_name2);
+    let mut tmp_name = name2.to_owned();
+    tmp_name.push(".dbl");
+    let tmp_name = file_name(dir2, &tmp_name);
+    if fs::hard_link(file_name(dir1, name1), &tmp_name).is_ok() {
+        fs::rename(tmp_name, file_name2).is_ok()
     } else {
         let _ = fs::remove_file(tmp_name);
+        false
     }
 }
 
 /// compare two files
 /// play it safe, just pretend the files differ on any error
-fn file_cmp(dir1: &PathBuf, name1: &str, dir2: &PathBuf, name2: &str) -> bool {
-    let file1 = match File::open(file_name(dir1, name1)) {
-        Ok(stream) => stream,
+fn file_cmp(dir1: &Path, name1: &OsStr, dir2: &Path, name2: &OsStr) -> bool {
+    let mut reader1 = match Reader::open(&file_name(dir1, name1)) {
+        Ok(reader) => reader,
         _ => {
             return false;
         }
     };
-    let file2 = match File::open(file_name(dir2, name2)) {
-        Ok(stream) => stream,
+    let file_name2 = file_name(dir2, name2);
+    progress::activity(Phase::Compare, &file_name2);
+    let mut reader2 = match Reader::open(&file_name2) {
+        Ok(reader) => reader,
         _ => {
             return false;
         }
     };
-    unsafe {
-        match Mmap::map(&file1) {
-            Ok(map1) => match Mmap::map(&file2) {
-                Ok(map2) => map1.chunks(65536).eq(map2.chunks(65536)),
-                _ => false,
-            },
-            _ => false,
+    let chunk_len = reader1
+        .chunk_len(COMPARE_CHUNK)
+        .max(reader2.chunk_len(COMPARE_CHUNK));
+    let mut offset = 0;
+    let equal = loop {
+        match (
+            reader1.read_at(offset, chunk_len),
+            reader2.read_at(offset, chunk_len),
+        ) {
+            (Ok(chunk1), Ok(chunk2)) if chunk1 == chunk2 => {
+                if chunk1.is_empty() {
+                    break true;
+                }
+                progress::read(2 * chunk1.len() as u64);
+                offset += chunk1.len() as u64;
+            }
+            _ => break false,
         }
-    }
+    };
+    // a file that shrank while reading may have looked equal to the other one
+    let intact1 = reader1.check().is_ok();
+    let intact2 = reader2.check().is_ok();
+    equal && intact1 && intact2
 }
 
 /// provide a replacement for inodes as unique ids on windows
@@ -614,7 +1486,7 @@ fn file_cmp(dir1: &PathBuf, name1: &str, dir2: &PathBuf, name2: &str) -> bool {
 // hard linked files can be identified by getting FindFirstFileName on them - linked files share that property
 // the following code is ugly due to the conversions needed between Windows API and native Rust strings
 #[cfg(windows)]
-fn windows_id(dir: &PathBuf, name: &str) -> FileId {
+fn windows_id(dir: &Path, name: &OsStr) -> FileId {
     use windows::{
         core::*,
         Win32::Storage::FileSystem::{FindClose, FindFirstFileNameW},
@@ -661,88 +1533,134 @@ fn windows_id(dir: &PathBuf, name: &str) -> FileId {
     }
 }
 
-/// find all files with min_size <= size <= max_size below dir
+/// find all files below dir that pass the filter
 fn find_files(
-    dir: &PathBuf,
+    dir: &Path,
     all_dirs: &mut Vec<PathBuf>,
     files: &mut Vec<FileInfo>,
-    min_size: u64,
-    max_size: u64,
-    exclude_files: &Vec<glob::Pattern>,
-    exclude_dirs: &Vec<glob::Pattern>,
+    filter: &Filter,
+    ignores: &mut IgnoreStack,
+    mut cache: Option<&mut ScanCache>,
 ) {
-    if let Ok(entries) = fs::read_dir(dir) {
-        let dir_index = all_dirs.len();
-        // TODO: postpone saving of directory path on stack, only store it when we also store files
-        // requires BFS which we can't guarantee
-        all_dirs.push(dir.clone());
-
-        'entries: for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                let metadata = match fs::symlink_metadata(&path) {
-                    Ok(metadata) => metadata,
-                    Err(_) => continue,
-                };
-                // do not follow symbolic links, junctions or mount points
-                if metadata.is_symlink() {
-                    #[cfg(debug_assertions)]
-                    println!("skipping symlink {}", path.display());
-                    continue;
-                }
+    // directories that did not change since the last run are not read again
+    let (cached, mtime) = match cache {
+        Some(ref mut cache) => cache.lookup(dir),
+        None => (None, 0),
+    };
+    let (dir_files, subdirs) = match cached {
+        Some(listing) => (listing.files, listing.subdirs),
+        None => match read_listing(dir) {
+            Some(listing) => listing,
+            None => return,
+        },
+    };
+    progress::scanning(dir, files.len());
+    let has_ignore_file = ignores.enter(dir);
+    let dir_index = all_dirs.len();
+    // TODO: postpone saving of directory path on stack, only store it when we also store files
+    // requires BFS which we can't guarantee
+    all_dirs.push(dir.to_path_buf());
 
-                for ignore_pattern in exclude_files {
-                    if ignore_pattern
-                        .matches(&path.file_name().unwrap().to_string_lossy().into_owned())
-                    {
-                        continue 'entries;
-                    }
-                }
-                if metadata.is_file() {
-                    if metadata.len() < min_size || metadata.len() > max_size {
-                        continue;
-                    }
-                    #[cfg(unix)]
-                    let id = metadata.ino() as FileId;
-                    #[cfg(windows)]
-                    let id = 0; /* we defer computation of uniq id on windows as it is costly, and we only need it for duplicate candidates */
-                    let name = path.file_name().unwrap().to_string_lossy().into_owned();
-                    let file_info = FileInfo {
-                        name,
-                        dir_index,
-                        size: metadata.len(),
-                        id,
-                    };
-                    files.push(file_info);
-                } else if metadata.is_dir() {
-                    // recurse here
-                    for ignore_pattern in exclude_dirs {
-                        if ignore_pattern
-                            .matches(&path.file_name().unwrap().to_string_lossy().into_owned())
-                        {
-                            continue 'entries;
-                        }
-                    }
-                    // check for ignore mark
-                    let mut ignore_path = path.clone();
-                    ignore_path.push(".keep_duplicates");
-                    if let Ok(_) = fs::symlink_metadata(&ignore_path) {
-                        println!("skipping {} - has .keep_duplicates", path.display());
-                    } else {
-                        find_files(
-                            &path,
-                            all_dirs,
-                            files,
-                            min_size,
-                            max_size,
-                            &exclude_files,
-                            &exclude_dirs,
-                        );
-                    }
-                }
-            }
+    for file in &dir_files {
+        let path = dir.join(&file.name);
+        if !filter.accepts_name(&path, true)
+            || ignores.is_ignored(&path, false)
+            || !filter.accepts_size_time(file.size, file.time(filter.age_by))
+        {
+            continue;
+        }
+        files.push(FileInfo {
+            name: file.name.clone(),
+            dir_index,
+            size: file.size,
+            id: file.id,
+        });
+    }
+    for subdir in &subdirs {
+        let path = dir.join(subdir);
+        if !filter.accepts_name(&path, false) || ignores.is_ignored(&path, true) {
+            continue;
+        }
+        // recurse here
+        // check for ignore mark
+        let mut ignore_path = path.clone();
+        ignore_path.push(".keep_duplicates");
+        if fs::symlink_metadata(&ignore_path).is_ok() {
+            progress::finish();
+            eprintln!("skipping {} - has .keep_duplicates", path.display());
+        } else {
+            find_files(
+                &path,
+                all_dirs,
+                files,
+                filter,
+                ignores,
+                cache.as_deref_mut(),
+            );
         }
     }
+    if has_ignore_file {
+        ignores.leave();
+    }
+    if let Some(cache) = cache {
+        cache.store(dir, mtime, dir_files, subdirs);
+    }
+}
+
+/// the file still has the size it was listed with and passes the filter with its current times
+fn still_candidate(dir: &Path, file: &FileInfo, filter: &Filter) -> bool {
+    let metadata = match fs::symlink_metadata(file_name(dir, &file.name)) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    #[cfg(unix)]
+    if metadata.ino() as FileId != file.id {
+        return false;
+    }
+    metadata.is_file()
+        && metadata.len() == file.size
+        && filter.accepts_size_time(metadata.len(), filter::file_time(&metadata, filter.age_by))
+}
+
+/// all regular files and subdirectories of dir, unfiltered
+fn read_listing(dir: &Path) -> Option<(Vec<CachedFile>, Vec<OsString>)> {
+    let entries = fs::read_dir(dir).ok()?;
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        // do not follow symbolic links, junctions or mount points
+        if metadata.is_symlink() {
+            #[cfg(debug_assertions)]
+            eprintln!("skipping symlink {}", path.display());
+            continue;
+        }
+        if metadata.is_file() {
+            #[cfg(unix)]
+            let id = metadata.ino() as FileId;
+            #[cfg(windows)]
+            let id = 0; /* we defer computation of uniq id on windows as it is costly, and we only need it for duplicate candidates */
+            files.push(CachedFile::new(entry.file_name(), id, &metadata));
+        } else if metadata.is_dir() {
+            subdirs.push(entry.file_name());
+        }
+    }
+    Some((files, subdirs))
+}
+
+/// full hashes of already hashed files, keyed by unique id and size
+type HashCache = HashMap<(FileId, u64), FullHash>;
+
+/// everything a run found, kept for watch and daemon mode
+struct Index {
+    all_dirs: Vec<PathBuf>,
+    // sorted by size
+    files: Vec<FileInfo>,
+    full_hash_cache: HashCache,
 }
 
 #[derive(Debug)]
@@ -750,5 +1668,5 @@ struct FileInfo {
     dir_index: usize,
     id: FileId,
     size: u64,
-    name: String,
+    name: OsString,
 }
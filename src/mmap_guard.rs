@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+
+// mappings that are read at the same time, lockstep compares use up to 8
+const SLOT_COUNT: usize = 32;
+
+/// a registered mapping, start is 0 for free slots
+struct Slot {
+    start: AtomicUsize,
+    len: AtomicUsize,
+    faulted: AtomicBool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE: Slot = Slot {
+    start: AtomicUsize::new(0),
+    len: AtomicUsize::new(0),
+    faulted: AtomicBool::new(false),
+};
+
+// the signal handler can't take locks, hence a fixed table of atomics
+static SLOTS: [Slot; SLOT_COUNT] = [FREE; SLOT_COUNT];
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(4096);
+static INSTALL: Once = Once::new();
+
+// reading a mapped page beyond the end of a file that shrank raises SIGBUS, which kills the
+// process by default. If the page belongs to a registered mapping, it is replaced by a page of
+// zeros, the read continues and the reader finds the fault flag afterwards.
+extern "C" fn on_sigbus(_signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let address = unsafe { (*info).si_addr() } as usize;
+    for slot in &SLOTS {
+        let start = slot.start.load(Ordering::SeqCst);
+        if start == 0 || address < start || address - start >= slot.len.load(Ordering::SeqCst) {
+            continue;
+        }
+        let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+        let page = address & !(page_size - 1);
+        let zeros = unsafe {
+            libc::mmap(
+                page as *mut libc::c_void,
+                page_size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        if zeros != libc::MAP_FAILED {
+            slot.faulted.store(true, Ordering::SeqCst);
+            return;
+        }
+    }
+    // not one of ours, the instruction faults again and the process dies as usual
+    unsafe {
+        libc::signal(libc::SIGBUS, libc::SIG_DFL);
+    }
+}
+
+fn install() {
+    INSTALL.call_once(|| unsafe {
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE);
+        if page_size > 0 {
+            PAGE_SIZE.store(page_size as usize, Ordering::Relaxed);
+        }
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigbus as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGBUS, &action, std::ptr::null_mut());
+    });
+}
+
+/// protect the mapping at start from SIGBUS, None when all slots are taken
+pub fn register(start: usize, len: usize) -> Option<usize> {
+    install();
+    for (i, slot) in SLOTS.iter().enumerate() {
+        // the length claims the slot, the start makes it visible to the handler
+        if slot
+            .len
+            .compare_exchange(0, len, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            slot.faulted.store(false, Ordering::SeqCst);
+            slot.start.store(start, Ordering::SeqCst);
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// a page of the mapping was beyond the end of the file
+pub fn faulted(slot: usize) -> bool {
+    SLOTS[slot].faulted.load(Ordering::SeqCst)
+}
+
+/// release the slot before the mapping goes away
+pub fn unregister(slot: usize) {
+    SLOTS[slot].start.store(0, Ordering::SeqCst);
+    SLOTS[slot].len.store(0, Ordering::SeqCst);
+}
@@ -0,0 +1,88 @@
+use crate::scan_cache::path_bytes;
+use crate::DuplicateSet;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// stdout carries paths or sets for other programs, text for humans goes to stderr then
+static MACHINE_READABLE: AtomicBool = AtomicBool::new(false);
+
+/// keep stdout free of text for humans, e.g. for --print0
+pub fn set_machine_readable(machine_readable: bool) {
+    MACHINE_READABLE.store(machine_readable, Ordering::Relaxed);
+}
+
+pub fn machine_readable() -> bool {
+    MACHINE_READABLE.load(Ordering::Relaxed)
+}
+
+/// println! for text meant for humans, printed to stderr when stdout is machine readable
+macro_rules! textln {
+    ($($arg:tt)*) => {
+        if $crate::output::machine_readable() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub(crate) use textln;
+
+/// print a path with all its bytes followed by NUL, like find -print0
+pub fn print0(path: &Path) {
+    let mut out = std::io::stdout().lock();
+    out.write_all(&path_bytes(path.as_os_str()))
+        .and_then(|_| out.write_all(b"\0"))
+        .expect("stdout write");
+}
+
+/// print all files of a duplicate set with print0(), the set ends with another NUL
+pub fn print0_set(set: &DuplicateSet) {
+    for file in &set.files {
+        print0(file);
+    }
+    std::io::stdout()
+        .lock()
+        .write_all(b"\0")
+        .expect("stdout write");
+}
+
+/// print duplicate sets in the format of fdupes, each set is followed by a blank line
+pub fn print_fdupes(sets: &[DuplicateSet], size: bool) {
+    let mut out = std::io::stdout().lock();
+    for set in sets {
+        if size {
+            let plural = if set.size != 1 { 's' } else { ' ' };
+            writeln!(out, "{} byte{plural} each:", set.size).expect("stdout write");
+        }
+        for file in &set.files {
+            writeln!(out, "{}", file.display()).expect("stdout write");
+        }
+        writeln!(out).expect("stdout write");
+    }
+}
+
+/// print the summary line of fdupes --summarize
+pub fn print_summary(sets: &[DuplicateSet]) {
+    if sets.is_empty() {
+        println!("No duplicates found.");
+        return;
+    }
+    let files: usize = sets.iter().map(|set| set.files.len() - 1).sum();
+    let bytes: u64 = sets
+        .iter()
+        .map(|set| set.size * (set.files.len() as u64 - 1))
+        .sum();
+    // fdupes uses decimal units with one fractional digit
+    let occupying = if bytes < 1000 {
+        format!("{bytes} bytes")
+    } else if bytes <= 1_000_000 {
+        format!("{:.1} kilobytes", bytes as f64 / 1000.0)
+    } else {
+        format!("{:.1} megabytes", bytes as f64 / 1_000_000.0)
+    };
+    println!(
+        "{files} duplicate files (in {} sets), occupying {occupying}",
+        sets.len()
+    );
+}
@@ -0,0 +1,195 @@
+use crate::units::kmgt;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// redrawing more often only costs time and nobody can read it anyway
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+// longest tail of the current file name shown
+const MAX_FILE_LEN: usize = 40;
+
+/// what is going on right now
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Scan,
+    Group,
+    PeekHash,
+    Sample,
+    FullHash,
+    Compare,
+    Link,
+}
+
+impl Phase {
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Scan => "scanning",
+            Phase::Group => "grouping",
+            Phase::PeekHash => "peek hashing",
+            Phase::Sample => "sampling",
+            Phase::FullHash => "full hashing",
+            Phase::Compare => "comparing",
+            Phase::Link => "linking",
+        }
+    }
+}
+
+struct Progress {
+    phase: Phase,
+    current: String,
+    // overall position, files and bytes of the sorted file list that are done
+    files_done: usize,
+    files_total: usize,
+    position: u64,
+    total: u64,
+    position_start: Instant,
+    // bytes actually read from disk, for the throughput
+    read: u64,
+    read_start: Instant,
+    last_draw: Option<Instant>,
+}
+
+// None when progress is disabled, progress is reported from deep inside the hash and
+// compare functions which should not need to pass it around
+static PROGRESS: Mutex<Option<Progress>> = Mutex::new(None);
+
+/// enable progress output, it is only shown when stdout is a terminal
+pub fn init(enabled: bool) {
+    let now = Instant::now();
+    *PROGRESS.lock().unwrap() = (enabled && std::io::stdout().is_terminal()).then(|| Progress {
+        phase: Phase::Scan,
+        current: String::new(),
+        files_done: 0,
+        files_total: 0,
+        position: 0,
+        total: 0,
+        position_start: now,
+        read: 0,
+        read_start: now,
+        last_draw: None,
+    });
+}
+
+fn update(force: bool, change: impl FnOnce(&mut Progress)) {
+    if let Some(ref mut progress) = *PROGRESS.lock().unwrap() {
+        change(progress);
+        let due = progress
+            .last_draw
+            .is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL);
+        if force || due {
+            progress.draw();
+        }
+    }
+}
+
+/// a directory is being scanned, files is the number of files found so far
+pub fn scanning(dir: &Path, files: usize) {
+    update(false, |progress| {
+        progress.phase = Phase::Scan;
+        progress.files_done = files;
+        progress.current = dir.display().to_string();
+    });
+}
+
+/// start working through the sorted file list
+pub fn start_grouping(files: usize, bytes: u64) {
+    update(true, |progress| {
+        progress.phase = Phase::Group;
+        progress.files_done = 0;
+        progress.files_total = files;
+        progress.position = 0;
+        progress.total = bytes;
+        progress.position_start = Instant::now();
+        progress.read = 0;
+        progress.read_start = Instant::now();
+        progress.current.clear();
+    });
+}
+
+/// files and bytes of the sorted file list that are done
+pub fn position(files: usize, bytes: u64) {
+    update(false, |progress| {
+        progress.phase = Phase::Group;
+        progress.files_done = files;
+        progress.position = bytes;
+    });
+}
+
+/// a file is being hashed, compared or linked
+pub fn activity(phase: Phase, file: &Path) {
+    update(false, |progress| {
+        progress.phase = phase;
+        progress.current = file.display().to_string();
+    });
+}
+
+/// bytes were read from disk, long hashes and compares call this repeatedly
+pub fn read(bytes: u64) {
+    update(false, |progress| progress.read += bytes);
+}
+
+/// clear the progress line so that regular output starts on an empty line
+pub fn finish() {
+    if let Some(ref mut progress) = *PROGRESS.lock().unwrap() {
+        if progress.last_draw.is_some() {
+            print!("\r\x1b[K");
+            let _ = std::io::stdout().flush();
+            progress.last_draw = None;
+        }
+    }
+}
+
+impl Progress {
+    fn draw(&mut self) {
+        let mut line = format!("{}: ", self.phase.label());
+        if self.phase == Phase::Scan {
+            line += &format!("{} files", self.files_done);
+        } else {
+            let percent = (100 * self.position).checked_div(self.total).unwrap_or(100);
+            line += &format!(
+                "{percent}% ({}/{} files, {}/{})",
+                self.files_done,
+                self.files_total,
+                kmgt(self.position),
+                kmgt(self.total)
+            );
+            let read_secs = self.read_start.elapsed().as_secs_f64();
+            if read_secs > 0.0 {
+                line += &format!(", {}/s", kmgt((self.read as f64 / read_secs) as u64));
+            }
+            // remaining time assuming the remaining bytes take as long as the ones done so far
+            let elapsed = self.position_start.elapsed().as_secs_f64();
+            if self.position > 0 && self.position < self.total {
+                let remaining =
+                    elapsed * (self.total - self.position) as f64 / self.position as f64;
+                line += &format!(", ETA {}", eta(remaining));
+            }
+        }
+        if !self.current.is_empty() {
+            let start = self
+                .current
+                .char_indices()
+                .rev()
+                .nth(MAX_FILE_LEN - 1)
+                .map_or(0, |(i, _)| i);
+            let ellipsis = if start > 0 { "..." } else { "" };
+            line += &format!(" {ellipsis}{}", &self.current[start..]);
+        }
+        print!("\r{line}\x1b[K");
+        let _ = std::io::stdout().flush();
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+fn eta(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m", seconds / 3600, seconds / 60 % 60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
@@ -0,0 +1,219 @@
+use crate::progress;
+use clap::ValueEnum;
+use memmap::Mmap;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// how file contents are accessed for hashing and comparing
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum IoMode {
+    // read() for files on remote file systems, memory mapping otherwise
+    Auto,
+    Mmap,
+    Read,
+}
+
+/// buffer size for spinning disks, long reads keep seeks between files rare
+pub const HDD_BUFFER_SIZE: u64 = 8 << 20;
+
+struct Settings {
+    mode: IoMode,
+    buffer_size: usize,
+}
+
+// chosen once at startup like the hash algorithms
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// select the access mode and the buffer size of read()
+pub fn init(mode: IoMode, buffer_size: u64) {
+    let buffer_size = usize::try_from(buffer_size).unwrap_or(usize::MAX).max(4096);
+    let _ = SETTINGS.set(Settings { mode, buffer_size });
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        mode: IoMode::Auto,
+        buffer_size: 1 << 20,
+    })
+}
+
+// network file systems may fail mapped pages when the server goes away, which kills the process
+#[cfg(target_os = "linux")]
+fn is_remote(file: &File) -> bool {
+    use std::os::fd::AsRawFd;
+    const REMOTE_MAGICS: [u64; 10] = [
+        0x6969,     // NFS
+        0x517b,     // SMB
+        0xff534d42, // CIFS
+        0xfe534d42, // SMB2
+        0x65735546, // FUSE, e.g. sshfs
+        0x00c36400, // Ceph
+        0x5346414f, // AFS
+        0x01021997, // 9P
+        0x73757245, // Coda
+        0x564c,     // NCP
+    ];
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut stat) } != 0 {
+        return false;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    let magic = stat.f_type as u64;
+    REMOTE_MAGICS.contains(&magic)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_remote(_file: &File) -> bool {
+    false
+}
+
+enum Access {
+    Map(Mmap),
+    // empty files can't be mapped
+    Empty,
+    Read(Vec<u8>),
+}
+
+// files that shrank while they were read
+static SHRUNK: AtomicUsize = AtomicUsize::new(0);
+
+/// number of files that shrank while they were read
+pub fn shrunk() -> usize {
+    SHRUNK.load(Ordering::Relaxed)
+}
+
+#[cfg(target_os = "linux")]
+fn guard(map: &Mmap) -> Option<usize> {
+    crate::mmap_guard::register(map.as_ptr() as usize, map.len())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn guard(_map: &Mmap) -> Option<usize> {
+    None
+}
+
+/// an opened file whose content is read in chunks at given offsets
+pub struct Reader {
+    file: File,
+    access: Access,
+    path: PathBuf,
+    // length when opened
+    size: u64,
+    // slot of the SIGBUS guard of the mapping
+    slot: Option<usize>,
+    // a read ended before the length the file had when opened
+    short: bool,
+}
+
+impl Reader {
+    pub fn open(path: &Path) -> std::io::Result<Reader> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let settings = settings();
+        let read = match settings.mode {
+            IoMode::Auto => is_remote(&file),
+            IoMode::Mmap => false,
+            IoMode::Read => true,
+        };
+        let mut slot = None;
+        let access = if read {
+            Access::Read(Vec::new())
+        } else if size == 0 {
+            Access::Empty
+        } else {
+            let map = unsafe { Mmap::map(&file)? };
+            slot = guard(&map);
+            // without guard a file truncated meanwhile would kill the process
+            if slot.is_none() && cfg!(target_os = "linux") {
+                Access::Read(Vec::new())
+            } else {
+                Access::Map(map)
+            }
+        };
+        Ok(Reader {
+            file,
+            access,
+            path: path.to_path_buf(),
+            size,
+            slot,
+            short: false,
+        })
+    }
+
+    /// preferred length of chunks, mapped files are best read in large chunks,
+    /// otherwise the buffer size is used
+    pub fn chunk_len(&self, mapped: usize) -> usize {
+        match self.access {
+            Access::Read(..) => settings().buffer_size,
+            _ => mapped,
+        }
+    }
+
+    /// up to len bytes at offset, fewer at the end of the file or if the file shrank meanwhile
+    pub fn read_at(&mut self, offset: u64, len: usize) -> std::io::Result<&[u8]> {
+        match self.access {
+            Access::Map(ref map) => {
+                let start = usize::try_from(offset).unwrap_or(usize::MAX).min(map.len());
+                let end = start.saturating_add(len).min(map.len());
+                Ok(&map[start..end])
+            }
+            Access::Empty => Ok(&[]),
+            Access::Read(ref mut buffer) => {
+                // like mappings, reads end at the length when opened, small files get small buffers
+                let rest = usize::try_from(self.size.saturating_sub(offset)).unwrap_or(usize::MAX);
+                let len = len.min(rest);
+                buffer.resize(len, 0);
+                self.file.seek(SeekFrom::Start(offset))?;
+                let mut filled = 0;
+                while filled < len {
+                    match self.file.read(&mut buffer[filled..]) {
+                        Ok(0) => break,
+                        Ok(n) => filled += n,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                if filled < len && offset + (filled as u64) < self.size {
+                    self.short = true;
+                }
+                Ok(&buffer[..filled])
+            }
+        }
+    }
+
+    /// fails if the file shrank since it was opened, the content read is not to be trusted then
+    pub fn check(&self) -> std::io::Result<()> {
+        #[cfg(target_os = "linux")]
+        let faulted = self.slot.is_some_and(crate::mmap_guard::faulted);
+        #[cfg(not(target_os = "linux"))]
+        let faulted = false;
+        let shrank = faulted
+            || self.short
+            || self
+                .file
+                .metadata()
+                .map_or(true, |metadata| metadata.len() < self.size);
+        if !shrank {
+            return Ok(());
+        }
+        SHRUNK.fetch_add(1, Ordering::Relaxed);
+        progress::finish();
+        eprintln!("{} shrank while being read, skipped", self.path.display());
+        Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "file shrank while being read",
+        ))
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(slot) = self.slot {
+            crate::mmap_guard::unregister(slot);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Result};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// makes temporary names unique within this process
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// names tried before giving up, other files only take a name by accident
+const ATTEMPTS: usize = 100;
+
+/// create a new file next to path under a name no other file has
+/// existing files are never opened, so nobody else's file is overwritten or removed
+fn create_temp(path: &Path) -> Result<(OsString, File)> {
+    let mut result = Err(ErrorKind::AlreadyExists.into());
+    for _ in 0..ATTEMPTS {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(
+            ".{}-{}.tmp",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        result = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .map(|file| (temp, file));
+        match result {
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            _ => break,
+        }
+    }
+    result
+}
+
+/// replace path by a file whose content write puts into a new temporary file
+/// the new content is synced to disk before the rename, so readers and crashes
+/// see either the old or the complete new file, never a partially written one
+pub fn replace_file(path: &Path, write: impl FnOnce(&mut File) -> Result<()>) -> Result<()> {
+    let (temp, mut file) = create_temp(path)?;
+    let result = write(&mut file)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        // the temporary file is ours, it was created above
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
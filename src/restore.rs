@@ -0,0 +1,102 @@
+use crate::output::textln;
+use crate::replace::replace_file;
+use crate::roots::Roots;
+use crate::units::kmgt;
+use crate::{file_name, Args, Index};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::Path;
+
+/// replace hard links among the indexed files by independent copies, the first or a read-only name keeps the original
+pub fn run(args: &Args, index: &mut Index) {
+    #[cfg(windows)]
+    for file in index.files.iter_mut() {
+        file.id = crate::windows_id(&index.all_dirs[file.dir_index], &file.name);
+    }
+    index.files.sort_unstable_by_key(|f| (f.size, f.id));
+    // files below read-only and reference roots are never replaced
+    let roots = Roots::new(args);
+    let writable: Vec<bool> = index
+        .all_dirs
+        .iter()
+        .map(|dir| roots.writable(dir))
+        .collect();
+    let mut restored = 0;
+    let mut restored_size = 0;
+    for links in index
+        .files
+        .chunk_by(|a, b| a.size == b.size && a.id == b.id)
+    {
+        // fileid 0 indicates I/O error
+        if links[0].id == 0 {
+            continue;
+        }
+        // a read-only link keeps the content, otherwise the first one does
+        let keep_first = links.iter().all(|file| writable[file.dir_index]);
+        for file in links.iter().skip(keep_first as usize) {
+            if !writable[file.dir_index] {
+                continue;
+            }
+            let dir = &index.all_dirs[file.dir_index];
+            match unlink(dir, &file.name) {
+                Ok(()) => {
+                    restored += 1;
+                    restored_size += file.size;
+                    if args.report_duplicates {
+                        textln!("restored {}", file_name(dir, &file.name).display());
+                    }
+                }
+                Err(e) => eprintln!("{:?} restoring {}", e, file_name(dir, &file.name).display()),
+            }
+        }
+    }
+    textln!(
+        "restored {restored} hard links, using {} more space",
+        kmgt(restored_size)
+    );
+}
+
+/// replace the file by a copy of itself that no longer shares its content with other links
+// like link() the copy is created next to the file first and then renamed over it
+fn unlink(dir: &Path, name: &OsStr) -> std::io::Result<()> {
+    let path = file_name(dir, name);
+    let mut original = File::open(&path)?;
+    let metadata = original.metadata()?;
+    replace_file(&path, |copy| {
+        std::io::copy(&mut original, copy)?;
+        copy.set_permissions(metadata.permissions())?;
+        copy.set_modified(metadata.modified()?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use std::fs;
+
+    #[cfg(unix)]
+    #[test]
+    fn unlink_keeps_other_files() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let dir = TestDir::new("restore_unlink");
+        fs::write(dir.join("A"), "content").unwrap();
+        fs::set_permissions(dir.join("A"), fs::Permissions::from_mode(0o640)).unwrap();
+        fs::hard_link(dir.join("A"), dir.join("B")).unwrap();
+        // names a careless temporary file could take
+        fs::write(dir.join("A.tmp"), "precious A").unwrap();
+        fs::write(dir.join("A.dbl"), "precious A").unwrap();
+
+        unlink(dir.path(), OsStr::new("A")).unwrap();
+
+        let a = fs::metadata(dir.join("A")).unwrap();
+        let b = fs::metadata(dir.join("B")).unwrap();
+        assert_ne!(a.ino(), b.ino());
+        assert_eq!(a.permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read(dir.join("A")).unwrap(), b"content");
+        assert_eq!(fs::read(dir.join("B")).unwrap(), b"content");
+        assert_eq!(fs::read(dir.join("A.tmp")).unwrap(), b"precious A");
+        assert_eq!(fs::read(dir.join("A.dbl")).unwrap(), b"precious A");
+        assert_eq!(dir.entries(), vec!["A", "A.dbl", "A.tmp", "B"]);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::Args;
+use std::path::{Path, PathBuf};
+
+/// how the files below a scanned root may be used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    ReadWrite,
+    // given as -d ro:DIR, may serve as link source but is never replaced
+    ReadOnly,
+    // only used as link source, never replaced
+    Reference,
+}
+
+/// prefix of -d roots whose files must never be replaced
+pub const READ_ONLY_PREFIX: &str = "ro:";
+
+/// strip the read-only prefix from the given roots and return the read-only ones
+pub fn strip_read_only(directories: &mut [PathBuf]) -> Vec<PathBuf> {
+    let mut read_only = Vec::new();
+    for dir in directories.iter_mut() {
+        if let Some(path) = dir.to_str().and_then(|d| d.strip_prefix(READ_ONLY_PREFIX)) {
+            *dir = PathBuf::from(path);
+            read_only.push(dir.clone());
+        }
+    }
+    read_only
+}
+
+/// the scanned roots and their access
+pub struct Roots {
+    roots: Vec<(PathBuf, Access)>,
+    // only duplicates of reference files are of interest
+    pub references: bool,
+}
+
+impl Roots {
+    pub fn new(args: &Args) -> Roots {
+        let roots = args
+            .reference
+            .iter()
+            .map(|root| (root.clone(), Access::Reference))
+            .chain(args.directories.iter().map(|root| {
+                if args.read_only.contains(root) {
+                    (root.clone(), Access::ReadOnly)
+                } else {
+                    (root.clone(), Access::ReadWrite)
+                }
+            }))
+            .collect();
+        Roots {
+            roots,
+            references: !args.reference.is_empty(),
+        }
+    }
+
+    /// access of the innermost root containing dir
+    pub fn access(&self, dir: &Path) -> Access {
+        self.roots
+            .iter()
+            .filter(|(root, _)| dir.starts_with(root))
+            .max_by_key(|(root, _)| root.components().count())
+            .map_or(Access::ReadWrite, |(_, access)| *access)
+    }
+
+    /// files in dir may be replaced by links
+    pub fn writable(&self, dir: &Path) -> bool {
+        self.access(dir) == Access::ReadWrite
+    }
+
+    /// files in dir are the preferred link sources
+    pub fn preferred(&self, dir: &Path) -> bool {
+        match self.access(dir) {
+            Access::ReadWrite => false,
+            Access::ReadOnly => !self.references,
+            Access::Reference => true,
+        }
+    }
+}
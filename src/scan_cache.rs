@@ -0,0 +1,420 @@
+use crate::filter::AgeBy;
+use crate::replace::replace_file;
+use crate::FileId;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 4] = b"FDSC";
+const VERSION: u32 = 1;
+
+// marks a time that is unknown or must not be trusted
+const NO_TIME: i64 = i64::MIN;
+
+// directories modified this close to the scan might change again within the same mtime tick
+const RACY_MTIME: Duration = Duration::from_secs(2);
+
+/// all entries of one directory as seen by the previous scan, before any filtering
+/// so that changed filter options do not invalidate the cache
+#[derive(Debug, Default)]
+pub struct DirListing {
+    mtime: i64,
+    pub files: Vec<CachedFile>,
+    pub subdirs: Vec<OsString>,
+}
+
+#[derive(Debug)]
+pub struct CachedFile {
+    pub name: OsString,
+    pub size: u64,
+    pub id: FileId,
+    mtime: i64,
+    ctime: i64,
+}
+
+impl CachedFile {
+    pub fn new(name: OsString, id: FileId, metadata: &Metadata) -> CachedFile {
+        CachedFile {
+            name,
+            size: metadata.len(),
+            id,
+            mtime: nanos(crate::filter::file_time(metadata, AgeBy::Mtime)),
+            ctime: nanos(crate::filter::file_time(metadata, AgeBy::Ctime)),
+        }
+    }
+
+    /// file time used for age checks
+    pub fn time(&self, age_by: AgeBy) -> Option<SystemTime> {
+        let nanos = match age_by {
+            AgeBy::Mtime => self.mtime,
+            AgeBy::Ctime => self.ctime,
+        };
+        if nanos == NO_TIME {
+            return None;
+        }
+        let offset = Duration::from_nanos(nanos.unsigned_abs());
+        if nanos < 0 {
+            UNIX_EPOCH.checked_sub(offset)
+        } else {
+            UNIX_EPOCH.checked_add(offset)
+        }
+    }
+}
+
+fn nanos(time: Option<SystemTime>) -> i64 {
+    let nanos = match time.map(|time| time.duration_since(UNIX_EPOCH)) {
+        Some(Ok(after)) => i64::try_from(after.as_nanos()).ok(),
+        Some(Err(before)) => i64::try_from(before.duration().as_nanos())
+            .ok()
+            .map(|nanos| -nanos),
+        None => None,
+    };
+    nanos.unwrap_or(NO_TIME)
+}
+
+/// directory listings of the previous run and the ones collected by the current run
+#[derive(Default)]
+pub struct ScanCache {
+    previous: HashMap<PathBuf, DirListing>,
+    current: HashMap<PathBuf, DirListing>,
+    start: Option<SystemTime>,
+    pub reused: usize,
+    pub read: usize,
+}
+
+impl ScanCache {
+    /// load the listings of the previous run, a missing file gives an empty cache
+    pub fn load(path: &Path) -> ScanCache {
+        let mut cache = ScanCache {
+            start: Some(SystemTime::now()),
+            ..Default::default()
+        };
+        match File::open(path) {
+            Ok(file) => match read_listings(&mut BufReader::new(file)) {
+                Ok(previous) => cache.previous = previous,
+                Err(e) => eprintln!(
+                    "{:?} reading scan cache {}, scanning all directories",
+                    e,
+                    path.display()
+                ),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => eprintln!("{:?} opening scan cache {}", e, path.display()),
+        }
+        cache
+    }
+
+    /// listing of dir from the previous run if dir did not change since then
+    /// the modification time of dir is returned in any case to store it with a new listing
+    pub fn lookup(&mut self, dir: &Path) -> (Option<DirListing>, i64) {
+        let modified = fs::metadata(dir).and_then(|m| m.modified()).ok();
+        // a racy mtime is stored as unknown so that the next run reads the directory again
+        let mtime = match (modified, self.start) {
+            (Some(modified), Some(start)) if modified + RACY_MTIME < start => nanos(Some(modified)),
+            _ => NO_TIME,
+        };
+        if mtime != NO_TIME {
+            if let Some(listing) = self.previous.remove(dir) {
+                if listing.mtime == mtime {
+                    self.reused += 1;
+                    return (Some(listing), mtime);
+                }
+            }
+        }
+        self.read += 1;
+        (None, mtime)
+    }
+
+    /// remember the listing of dir for the next run
+    pub fn store(
+        &mut self,
+        dir: &Path,
+        mtime: i64,
+        files: Vec<CachedFile>,
+        subdirs: Vec<OsString>,
+    ) {
+        self.current.insert(
+            dir.to_path_buf(),
+            DirListing {
+                mtime,
+                files,
+                subdirs,
+            },
+        );
+    }
+
+    /// write the listings collected by this run, the listings of unscanned directories are dropped
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        // an interrupted run must not leave a truncated cache
+        replace_file(path, |file| self.write(file))
+    }
+
+    fn write(&self, file: &mut File) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.current.len() as u64).to_le_bytes())?;
+        for (dir, listing) in &self.current {
+            write_bytes(&mut writer, &path_bytes(dir.as_os_str()))?;
+            writer.write_all(&listing.mtime.to_le_bytes())?;
+            writer.write_all(&(listing.subdirs.len() as u64).to_le_bytes())?;
+            for subdir in &listing.subdirs {
+                write_bytes(&mut writer, &path_bytes(subdir))?;
+            }
+            writer.write_all(&(listing.files.len() as u64).to_le_bytes())?;
+            for file in &listing.files {
+                write_bytes(&mut writer, &path_bytes(&file.name))?;
+                writer.write_all(&file.size.to_le_bytes())?;
+                writer.write_all(&file.id.to_le_bytes())?;
+                writer.write_all(&file.mtime.to_le_bytes())?;
+                writer.write_all(&file.ctime.to_le_bytes())?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(unix)]
+pub fn path_bytes(path: &std::ffi::OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+// paths that are not valid unicode are mangled and will simply never match again
+#[cfg(not(unix))]
+pub fn path_bytes(path: &std::ffi::OsStr) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut buffer = [0; N];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_i64(reader: &mut impl Read) -> std::io::Result<i64> {
+    Ok(i64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_bytes(reader: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    // a corrupt length must not allocate unbounded memory up front
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_listings(reader: &mut impl Read) -> std::io::Result<HashMap<PathBuf, DirListing>> {
+    if &read_array::<4>(reader)? != MAGIC {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "not a scan cache",
+        ));
+    }
+    let version = u32::from_le_bytes(read_array(reader)?);
+    if version != VERSION {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported scan cache version {version}"),
+        ));
+    }
+    let mut listings = HashMap::new();
+    for _ in 0..read_u64(reader)? {
+        let dir = PathBuf::from(path_from_bytes(read_bytes(reader)?));
+        let mtime = read_i64(reader)?;
+        let mut listing = DirListing {
+            mtime,
+            ..Default::default()
+        };
+        for _ in 0..read_u64(reader)? {
+            listing.subdirs.push(path_from_bytes(read_bytes(reader)?));
+        }
+        for _ in 0..read_u64(reader)? {
+            listing.files.push(CachedFile {
+                name: path_from_bytes(read_bytes(reader)?),
+                size: read_u64(reader)?,
+                id: FileId::from_le_bytes(read_array(reader)?),
+                mtime: read_i64(reader)?,
+                ctime: read_i64(reader)?,
+            });
+        }
+        listings.insert(dir, listing);
+    }
+    Ok(listings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn cached_file(name: &str, size: u64, id: FileId, mtime: i64) -> CachedFile {
+        CachedFile {
+            name: OsString::from(name),
+            size,
+            id,
+            mtime,
+            ctime: NO_TIME,
+        }
+    }
+
+    // the directory with the saved sample cache and the content of the cache file
+    fn saved(name: &str) -> (TestDir, Vec<u8>) {
+        let mut cache = ScanCache::default();
+        cache.store(
+            Path::new("/data"),
+            1_700_000_000_000_000_000,
+            vec![
+                cached_file("a", 100, 7, 1_600_000_000_123_456_789),
+                cached_file("ünïcode", 0, 8, -5),
+            ],
+            vec![OsString::from("sub dir")],
+        );
+        cache.store(Path::new("/data/sub dir"), NO_TIME, vec![], vec![]);
+        let dir = TestDir::new(name);
+        cache.save(&dir.join("cache")).unwrap();
+        let bytes = fs::read(dir.join("cache")).unwrap();
+        (dir, bytes)
+    }
+
+    fn read(bytes: &[u8]) -> std::io::Result<HashMap<PathBuf, DirListing>> {
+        read_listings(&mut &bytes[..])
+    }
+
+    #[test]
+    fn round_trip() {
+        let (dir, bytes) = saved("scan_cache_round_trip");
+        let listings = read(&bytes).unwrap();
+        assert_eq!(listings.len(), 2);
+        let data = &listings[Path::new("/data")];
+        assert_eq!(data.mtime, 1_700_000_000_000_000_000);
+        assert_eq!(data.subdirs, vec![OsString::from("sub dir")]);
+        assert_eq!(data.files.len(), 2);
+        assert_eq!(data.files[0].name, "a");
+        assert_eq!(data.files[0].size, 100);
+        assert_eq!(data.files[0].id, 7);
+        assert_eq!(data.files[0].mtime, 1_600_000_000_123_456_789);
+        assert_eq!(data.files[0].ctime, NO_TIME);
+        assert_eq!(data.files[1].name, "ünïcode");
+        assert_eq!(data.files[1].mtime, -5);
+        let sub = &listings[Path::new("/data/sub dir")];
+        assert_eq!(sub.mtime, NO_TIME);
+        assert!(sub.files.is_empty() && sub.subdirs.is_empty());
+        // load() reads the same listings
+        assert_eq!(ScanCache::load(&dir.join("cache")).previous.len(), 2);
+        // no temporary file is left behind
+        assert_eq!(dir.entries(), vec!["cache"]);
+    }
+
+    #[test]
+    fn times() {
+        let file = cached_file("a", 0, 0, 1_500_000_000);
+        assert_eq!(
+            file.time(AgeBy::Mtime),
+            Some(UNIX_EPOCH + Duration::from_millis(1500))
+        );
+        assert_eq!(
+            cached_file("a", 0, 0, -1_000_000_000).time(AgeBy::Mtime),
+            UNIX_EPOCH.checked_sub(Duration::from_secs(1))
+        );
+        assert_eq!(file.time(AgeBy::Ctime), None);
+        assert_eq!(nanos(file.time(AgeBy::Mtime)), 1_500_000_000);
+        assert_eq!(nanos(None), NO_TIME);
+    }
+
+    #[test]
+    fn wrong_magic_or_version() {
+        let (_file, bytes) = saved("scan_cache_magic");
+        let mut wrong = bytes.clone();
+        wrong[0] = b'X';
+        assert!(read(&wrong).is_err());
+        let mut wrong = bytes;
+        wrong[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(read(&wrong).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let (dir, bytes) = saved("scan_cache_truncated");
+        for len in 0..bytes.len() {
+            assert!(read(&bytes[..len]).is_err(), "{len}");
+        }
+        // a damaged cache is ignored as a whole
+        fs::write(dir.join("cache"), &bytes[..bytes.len() - 1]).unwrap();
+        assert!(ScanCache::load(&dir.join("cache")).previous.is_empty());
+    }
+
+    #[test]
+    fn lengths_out_of_range() {
+        let (_file, bytes) = saved("scan_cache_lengths");
+        // number of directories and length of the first directory path
+        for offset in [8, 16] {
+            let mut wrong = bytes.clone();
+            wrong[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+            assert!(read(&wrong).is_err(), "{offset}");
+        }
+    }
+
+    #[test]
+    fn missing_cache_is_empty() {
+        let dir = TestDir::new("scan_cache_missing");
+        assert!(ScanCache::load(&dir.join("cache")).previous.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unchanged_directories_are_reused() {
+        let test_dir = TestDir::new("scan_cache_lookup");
+        let path = test_dir.join("cache");
+        let dir = test_dir.join("listed");
+        fs::create_dir(&dir).unwrap();
+        let set_mtime = |secs| {
+            File::open(&dir)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap()
+        };
+        set_mtime(1_000_000);
+        let mut cache = ScanCache::load(&path);
+        let (listing, mtime) = cache.lookup(&dir);
+        assert!(listing.is_none());
+        cache.store(&dir, mtime, vec![cached_file("a", 1, 2, 3)], vec![]);
+        cache.save(&path).unwrap();
+
+        let mut cache = ScanCache::load(&path);
+        let (listing, _) = cache.lookup(&dir);
+        assert_eq!(listing.unwrap().files[0].name, "a");
+        assert_eq!((cache.reused, cache.read), (1, 0));
+
+        set_mtime(2_000_000);
+        let mut cache = ScanCache::load(&path);
+        assert!(cache.lookup(&dir).0.is_none());
+        assert_eq!((cache.reused, cache.read), (0, 1));
+    }
+}
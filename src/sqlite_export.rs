@@ -0,0 +1,160 @@
+use crate::hash;
+use crate::{file_name, DuplicateSet, FileId, Index};
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE settings (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE directories (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL
+);
+CREATE TABLE files (
+    id INTEGER PRIMARY KEY,
+    directory_id INTEGER NOT NULL REFERENCES directories(id),
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    inode INTEGER NOT NULL
+);
+CREATE TABLE hashes (
+    inode INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    full_hash BLOB NOT NULL,
+    PRIMARY KEY (inode, size)
+);
+CREATE TABLE duplicate_sets (
+    id INTEGER PRIMARY KEY,
+    size INTEGER NOT NULL,
+    original_id INTEGER REFERENCES files(id)
+);
+CREATE TABLE duplicates (
+    set_id INTEGER NOT NULL REFERENCES duplicate_sets(id),
+    file_id INTEGER REFERENCES files(id),
+    action TEXT NOT NULL
+);
+CREATE INDEX files_directory ON files(directory_id);
+CREATE INDEX duplicates_set ON duplicates(set_id);
+";
+
+// marks databases written by find_doubles, "fdDB"
+const APPLICATION_ID: i32 = 0x6664_4442;
+
+// SQLite integers are signed 64 bit, larger values wrap around
+fn integer(value: u64) -> i64 {
+    value as i64
+}
+
+// on Windows only the lower half of the 128 bit file id is kept
+#[allow(clippy::unnecessary_cast)]
+fn inode(id: FileId) -> i64 {
+    integer(id as u64)
+}
+
+/// an empty file or a database written by an earlier export, which may be replaced
+/// exports written before the application id was set are recognized by their settings
+fn is_export(path: &Path) -> bool {
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == 0) {
+        return true;
+    }
+    let Ok(connection) = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) else {
+        return false;
+    };
+    let application_id = connection.query_row("PRAGMA application_id", [], |row| row.get(0));
+    application_id == Ok(APPLICATION_ID)
+        || connection
+            .query_row(
+                "SELECT count(*) FROM settings WHERE name = 'full_hash_algorithm'",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .is_ok_and(|count| count > 0)
+}
+
+/// write the scan results and the duplicates found to a new SQLite database at path
+/// an existing database of an earlier export is replaced, other files are left alone
+pub fn export(
+    path: &Path,
+    index: &Index,
+    sets: &[DuplicateSet],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if path.exists() {
+        if !is_export(path) {
+            return Err(format!("{} is no find_doubles database", path.display()).into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let mut connection = Connection::open(path)?;
+    connection.pragma_update(None, "application_id", APPLICATION_ID)?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(SCHEMA)?;
+    {
+        // hashes can only be compared with hashes of the same algorithm
+        let mut insert =
+            transaction.prepare("INSERT INTO settings (name, value) VALUES (?1, ?2)")?;
+        insert.execute(params![
+            "peek_hash_algorithm",
+            hash::peek_algorithm().name()
+        ])?;
+        insert.execute(params![
+            "full_hash_algorithm",
+            hash::full_algorithm().name()
+        ])?;
+        let mut insert =
+            transaction.prepare("INSERT INTO directories (id, path) VALUES (?1, ?2)")?;
+        for (i, dir) in index.all_dirs.iter().enumerate() {
+            insert.execute(params![i as i64, dir.to_string_lossy()])?;
+        }
+        // files of duplicate sets are referenced by path, look up their ids while inserting
+        let mut set_files: HashMap<&Path, i64> = sets
+            .iter()
+            .flat_map(|set| set.files.iter())
+            .map(|file| (file.as_path(), 0))
+            .collect();
+        let mut insert = transaction.prepare(
+            "INSERT INTO files (id, directory_id, name, size, inode) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (i, file) in index.files.iter().enumerate() {
+            let id = i as i64 + 1;
+            insert.execute(params![
+                id,
+                file.dir_index as i64,
+                file.name.to_string_lossy(),
+                integer(file.size),
+                inode(file.id)
+            ])?;
+            if !set_files.is_empty() {
+                if let Some(set_file) = set_files
+                    .get_mut(file_name(&index.all_dirs[file.dir_index], &file.name).as_path())
+                {
+                    *set_file = id;
+                }
+            }
+        }
+        let mut insert = transaction
+            .prepare("INSERT INTO hashes (inode, size, full_hash) VALUES (?1, ?2, ?3)")?;
+        let hash_len = hash::full_algorithm().digest_len();
+        for ((id, size), hash) in &index.full_hash_cache {
+            insert.execute(params![inode(*id), integer(*size), &hash[..hash_len]])?;
+        }
+        // files that vanished from the index, e.g. in watch mode, have no id
+        let file_id = |file: &Path| set_files.get(file).copied().filter(|&id| id > 0);
+        let mut insert_set = transaction
+            .prepare("INSERT INTO duplicate_sets (id, size, original_id) VALUES (?1, ?2, ?3)")?;
+        let mut insert_duplicate = transaction
+            .prepare("INSERT INTO duplicates (set_id, file_id, action) VALUES (?1, ?2, ?3)")?;
+        for (i, set) in sets.iter().enumerate() {
+            let set_id = i as i64 + 1;
+            insert_set.execute(params![set_id, integer(set.size), file_id(&set.files[0])])?;
+            for (file, linked) in set.files.iter().zip(&set.linked).skip(1) {
+                let action = if *linked { "linked" } else { "none" };
+                insert_duplicate.execute(params![set_id, file_id(file), action])?;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
@@ -0,0 +1,269 @@
+use crate::output::{self, textln};
+use crate::replace::replace_file;
+use crate::units::kmgt;
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// machine readable formats of the statistics
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum StatsFormat {
+    Json,
+    // text exposition format, e.g. for the textfile collector of the node exporter
+    Prometheus,
+}
+
+// prefix of all Prometheus metrics
+const METRIC_PREFIX: &str = "find_doubles_";
+
+// fields that describe the scanned files, everything else counts work done during the run
+const GAUGES: [&str; 10] = [
+    "total_files",
+    "total_dirs",
+    "total_size",
+    "files_with_equals",
+    "sets_with_equals",
+    "fully_linked",
+    "old_link_save",
+    "already_shared",
+    "fully_shared",
+    "old_share_save",
+];
+
+// fields that hold a number of bytes
+const SIZES: [&str; 5] = [
+    "total_size",
+    "old_link_save",
+    "old_share_save",
+    "new_link_save",
+    "full_hash_size",
+];
+
+/// counters and timings of one run
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct Stats {
+    pub total_files: usize,
+    pub total_dirs: usize,
+    pub total_size: u64,
+    pub files_with_equals: usize,
+    pub sets_with_equals: usize,
+    pub fully_linked: usize,
+    pub old_link_save: u64,
+    // reflink clones found, sets that were completely shared and the space their sharing saves
+    pub already_shared: usize,
+    pub fully_shared: usize,
+    pub old_share_save: u64,
+    pub set_of_2: usize,
+    pub linked: usize,
+    pub new_link_save: u64,
+    pub file_compares: usize,
+    // groups of more than 2 runs compared in lockstep
+    pub stream_compares: usize,
+    pub peek_hashes: usize,
+    // candidates told apart by their peek hash or one of the sample stages
+    pub peek_eliminated: usize,
+    pub sample_hashes: usize,
+    pub tail_eliminated: usize,
+    pub middle_eliminated: usize,
+    pub spread_eliminated: usize,
+    pub full_hashes: usize,
+    pub full_hash_size: u64,
+    pub set_merges: usize,
+    pub merged_files: usize,
+    // files with equal full hashes that differed when compared with --paranoid
+    pub hash_collisions: usize,
+    // files that shrank while being read and were skipped
+    pub shrunk_files: usize,
+    // files from the scan cache or an index that changed since they were listed
+    pub changed_files: usize,
+    #[serde(serialize_with = "seconds")]
+    pub scan_time: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub sort_time: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub link_test_time: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub compare_time: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub peek_hash_time: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub sample_hash_time: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub full_hash_time: Duration,
+    #[serde(serialize_with = "seconds")]
+    pub total_time: Duration,
+}
+
+// durations are exported as fractional seconds
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl Stats {
+    /// print timings of the scan phase
+    pub fn print_scan(&self) {
+        textln!("Scanning of directories took {:?}", self.scan_time);
+        textln!("Sorting of files took {:?}", self.sort_time);
+    }
+
+    /// print counters and timings of the duplicate search
+    pub fn print(&self, link_duplicates: bool) {
+        #[cfg(windows)]
+        textln!("spent {:?} to get unique file ids", self.link_test_time);
+        textln!(
+            "{} files in {} sets of equal size grouped",
+            self.files_with_equals,
+            self.sets_with_equals
+        );
+        textln!(
+            "{} sets were already linked, saving {}",
+            self.fully_linked,
+            kmgt(self.old_link_save)
+        );
+        if self.already_shared > 0 {
+            textln!(
+                "{} files already shared their data as reflinks, {} sets completely, saving {}",
+                self.already_shared,
+                self.fully_shared,
+                kmgt(self.old_share_save)
+            );
+        }
+        if link_duplicates {
+            textln!(
+                "{} pairs compared, created {} new links saving {}",
+                self.set_of_2,
+                self.linked,
+                kmgt(self.new_link_save)
+            );
+        } else {
+            textln!(
+                "{} pairs compared, linking would save {}",
+                self.set_of_2,
+                kmgt(self.new_link_save)
+            );
+        }
+        textln!(
+            "spent {:?} comparing {} file pairs and {} small groups",
+            self.compare_time,
+            self.file_compares,
+            self.stream_compares
+        );
+        textln!(
+            "spent {:?} computing {} peek hashes",
+            self.peek_hash_time,
+            self.peek_hashes
+        );
+        textln!(
+            "spent {:?} computing {} sample hashes",
+            self.sample_hash_time,
+            self.sample_hashes
+        );
+        textln!(
+            "candidates eliminated by peek hash: {}, tail: {}, middle: {}, spread samples: {}",
+            self.peek_eliminated,
+            self.tail_eliminated,
+            self.middle_eliminated,
+            self.spread_eliminated
+        );
+        textln!(
+            "spent {:?} computing {} full hashes, ({})",
+            self.full_hash_time,
+            self.full_hashes,
+            kmgt(self.full_hash_size)
+        );
+        if self.merged_files > 0 {
+            textln!(
+                "merged {} files into {} existing sets",
+                self.merged_files,
+                self.set_merges
+            );
+        }
+        if self.shrunk_files > 0 {
+            textln!(
+                "{} files shrank while being read and were skipped",
+                self.shrunk_files
+            );
+        }
+        if self.changed_files > 0 {
+            textln!(
+                "{} files changed since they were listed and were skipped",
+                self.changed_files
+            );
+        }
+        if self.hash_collisions > 0 {
+            textln!(
+                "{} files with equal full hashes differed in content",
+                self.hash_collisions
+            );
+        }
+        textln!("Total time spent {:?}", self.total_time);
+    }
+
+    /// statistics in the given format
+    pub fn format(&self, format: StatsFormat) -> String {
+        match format {
+            StatsFormat::Json => {
+                serde_json::to_string_pretty(self).expect("stats serialization") + "\n"
+            }
+            StatsFormat::Prometheus => self.prometheus(),
+        }
+    }
+
+    fn prometheus(&self) -> String {
+        let mut text = String::new();
+        let value = serde_json::to_value(self).expect("stats serialization");
+        for (field, value) in value.as_object().into_iter().flatten() {
+            // names end in the base unit, seconds or bytes, and counters in _total
+            let mut name = match field.strip_suffix("_time") {
+                Some("total") => format!("{METRIC_PREFIX}run_seconds"),
+                Some(name) => format!("{METRIC_PREFIX}{name}_seconds"),
+                None => format!("{METRIC_PREFIX}{field}"),
+            };
+            if SIZES.contains(&field.as_str()) {
+                name += "_bytes";
+            }
+            let kind = if GAUGES.contains(&field.as_str()) {
+                "gauge"
+            } else {
+                name += "_total";
+                "counter"
+            };
+            let _ = writeln!(text, "# TYPE {name} {kind}");
+            let _ = writeln!(text, "{name} {value}");
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let _ = writeln!(
+            text,
+            "# TYPE {METRIC_PREFIX}last_run_timestamp_seconds gauge"
+        );
+        let _ = writeln!(
+            text,
+            "{METRIC_PREFIX}last_run_timestamp_seconds {}",
+            now.as_secs_f64()
+        );
+        text
+    }
+
+    /// print the statistics or write them to path
+    pub fn export(&self, format: StatsFormat, path: Option<&Path>) -> std::io::Result<()> {
+        let text = self.format(format);
+        match path {
+            Some(path) => replace_file(path, |file| {
+                std::io::Write::write_all(file, text.as_bytes())
+            }),
+            // not mixed into machine readable output of the duplicates
+            None if output::machine_readable() => {
+                eprint!("{text}");
+                Ok(())
+            }
+            None => {
+                print!("{text}");
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// parse a human-friendly duration like `90s`, `12h`, `7d` or `1w2d`
+/// a bare number is taken as seconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let mut total = 0_u64;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("expected a number in duration '{text}'"));
+        }
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("number too large in duration '{text}'"))?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "s" | "sec" => 1,
+            "m" | "min" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            unit => {
+                return Err(format!(
+                    "unknown unit '{unit}' in duration '{text}', use s, m, h, d or w"
+                ))
+            }
+        };
+        rest = &rest[unit_len..];
+        total = value
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| format!("duration '{text}' is too large"))?;
+    }
+    Ok(Duration::from_secs(total))
+}
+
+// selects decimal (SI) instead of binary (IEC) units for all size output
+static SI_UNITS: AtomicBool = AtomicBool::new(false);
+
+/// choose between decimal (kB, MB, ...) and binary (kiB, MiB, ...) units for kmgt()
+pub fn set_si_units(si: bool) {
+    SI_UNITS.store(si, Ordering::Relaxed);
+}
+
+/// nicely format number of bytes into human-readable form
+pub fn kmgt(bytes: u64) -> String {
+    let (base, units) = if SI_UNITS.load(Ordering::Relaxed) {
+        (1000, ["kB", "MB", "GB", "TB"])
+    } else {
+        (1024, ["kiB", "MiB", "GiB", "TiB"])
+    };
+    if bytes < base {
+        return format!("{bytes} B");
+    }
+    let mut mag = base;
+    for unit in &units[..units.len() - 1] {
+        if bytes < mag * base {
+            let f = (bytes % mag) * 10 / mag;
+            return format!("{}.{f} {unit}", bytes / mag);
+        }
+        mag *= base;
+    }
+    let f = (bytes % mag) * 10 / mag;
+    format!("{}.{f} {}", bytes / mag, units[units.len() - 1])
+}
+
+/// parse a size like `65536`, `64K`, `1.5MiB` or `2GB`
+/// single letter and IEC suffixes (K, KiB) are binary multiples, SI suffixes (kB, KB) decimal ones
+/// fractions are only accepted when they give a whole number of bytes
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let number_len = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, suffix) = text.split_at(number_len);
+    let (int_part, frac_part) = number.split_once('.').unwrap_or((number, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return Err(format!("expected a number in size '{text}'"));
+    }
+    let suffix = suffix.trim_start();
+    let mut chars = suffix.chars();
+    let exponent = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        None | Some('B') => 0,
+        Some('K') => 1,
+        Some('M') => 2,
+        Some('G') => 3,
+        Some('T') => 4,
+        Some('P') => 5,
+        Some('E') => 6,
+        Some(_) => return Err(format!("unknown unit '{suffix}' in size '{text}'")),
+    };
+    let base: u128 = match (exponent, chars.as_str()) {
+        (0, "") => 1,
+        (0, _) => return Err(format!("unknown unit '{suffix}' in size '{text}'")),
+        (_, "" | "i" | "iB" | "ib") => 1024,
+        (_, "B" | "b") => 1000,
+        _ => return Err(format!("unknown unit '{suffix}' in size '{text}'")),
+    };
+    let multiplier = base.pow(exponent);
+    let int_value: u128 = if int_part.is_empty() {
+        0
+    } else {
+        int_part
+            .parse()
+            .map_err(|_| format!("number too large in size '{text}'"))?
+    };
+    let mut bytes = int_value.checked_mul(multiplier);
+    if !frac_part.is_empty() {
+        if frac_part.contains('.') || frac_part.len() > 18 {
+            return Err(format!("invalid number in size '{text}'"));
+        }
+        let scale = 10_u128.pow(frac_part.len() as u32);
+        let frac_value: u128 = frac_part
+            .parse()
+            .map_err(|_| format!("invalid number in size '{text}'"))?;
+        // sizes are whole bytes, 1.5 or 1.1KiB would have to be rounded
+        if !(frac_value * multiplier).is_multiple_of(scale) {
+            return Err(format!("size '{text}' is not a whole number of bytes"));
+        }
+        bytes = bytes.and_then(|b| b.checked_add(frac_value * multiplier / scale));
+    }
+    bytes
+        .and_then(|b| u64::try_from(b).ok())
+        .ok_or_else(|| format!("size '{text}' is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("65536"), Ok(65536));
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("64KiB"), Ok(64 << 10));
+        assert_eq!(parse_size("64kB"), Ok(64_000));
+        assert_eq!(parse_size("64KB"), Ok(64_000));
+        assert_eq!(parse_size("1.5MiB"), Ok(3 << 19));
+        assert_eq!(parse_size("1.5M"), Ok(3 << 19));
+        assert_eq!(parse_size("2GB"), Ok(2_000_000_000));
+        assert_eq!(parse_size("1T"), Ok(1 << 40));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert_eq!(parse_size(".5K"), Ok(512));
+        assert_eq!(parse_size("1.0"), Ok(1));
+        assert_eq!(parse_size("1.25KiB"), Ok(1280));
+        assert_eq!(parse_size("0.001kB"), Ok(1));
+        assert_eq!(parse_size(" 12 M "), Ok(12 << 20));
+        assert_eq!(parse_size("15E"), Ok(15 << 60));
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn size_errors() {
+        for text in [
+            "", " ", ".", "B", "K", "1.5.3", "1x", "1KX", "1BB", "1BiB", "1iB", "-1", "1 .5",
+        ] {
+            assert!(parse_size(text).is_err(), "{text}");
+        }
+        // fractional bytes
+        for text in ["1.5", "1.5B", "0.1", "1.1KiB", "0.0001kB"] {
+            assert!(parse_size(text).is_err(), "{text}");
+        }
+        // overflow
+        assert!(parse_size("16E").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("99999999999999999999999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5min"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_duration("1w2d"), Ok(Duration::from_secs(9 * 86400)));
+        assert_eq!(parse_duration("1d12h"), Ok(Duration::from_secs(36 * 3600)));
+        assert_eq!(parse_duration(" 30s "), Ok(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn duration_errors() {
+        for text in ["", " ", "h", "5y", "1.5h", "1h-2m", "d1"] {
+            assert!(parse_duration(text).is_err(), "{text}");
+        }
+        // overflow
+        assert!(parse_duration("99999999999999999999s").is_err());
+        assert!(parse_duration("40000000000000w").is_err());
+        assert!(parse_duration("18446744073709551615s1s").is_err());
+    }
+
+    #[test]
+    fn formatting() {
+        set_si_units(false);
+        assert_eq!(kmgt(1023), "1023 B");
+        assert_eq!(kmgt(1536), "1.5 kiB");
+        assert_eq!(kmgt(3 << 40), "3.0 TiB");
+    }
+}
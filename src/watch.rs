@@ -0,0 +1,387 @@
+use crate::filter::{self, AgeBy, Filter};
+use crate::hash::FullHash;
+use crate::ignore_files::IgnoreStack;
+use crate::output::textln;
+use crate::roots::Roots;
+use crate::{file_cmp, find_files, full_hash, Actions, Args, FileId, FileInfo, Index};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// a file that changed recently
+struct Pending {
+    // time of the last event
+    changed: Instant,
+    // size when queued, a different size at the end of the settle time means it is still written
+    size: Option<u64>,
+    // written since it was last closed, writers may pause longer than the settle time
+    open: bool,
+}
+
+// how long to sleep when there are no new events
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// watches the scanned directories and links new files to existing duplicates as they appear
+pub struct Watcher<'a> {
+    args: &'a Args,
+    filter: Filter,
+    roots: Roots,
+    inotify: Inotify,
+    watched: HashMap<WatchDescriptor, usize>,
+    dir_indices: HashMap<PathBuf, usize>,
+    // files that changed recently, processed when they did not change for the settle time
+    pending: HashMap<PathBuf, Pending>,
+    settle: Duration,
+    buffer: [u8; 4096],
+    // new files are only queued, they are processed once this is false again
+    pub paused: bool,
+}
+
+/// watch all scanned directories and deduplicate new files once they settled
+/// this never returns unless inotify fails
+pub fn run(
+    args: &Args,
+    filter: &Filter,
+    actions: &mut Actions,
+    index: &mut Index,
+) -> std::io::Result<()> {
+    let mut watcher = Watcher::new(args, filter, index)?;
+    textln!(
+        "watching {} directories for new files",
+        watcher.watched.len()
+    );
+    loop {
+        if !watcher.poll(index, actions)? {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl<'a> Watcher<'a> {
+    pub fn new(args: &'a Args, filter: &Filter, index: &Index) -> std::io::Result<Watcher<'a>> {
+        let mut watcher = Watcher {
+            args,
+            filter: filter.clone(),
+            roots: Roots::new(args),
+            inotify: Inotify::init()?,
+            watched: HashMap::new(),
+            dir_indices: index
+                .all_dirs
+                .iter()
+                .enumerate()
+                .map(|(dir_index, dir)| (dir.clone(), dir_index))
+                .collect(),
+            pending: HashMap::new(),
+            // a file has to be unchanged for at least min_age before it passes the filter
+            settle: args.settle.max(args.min_age.unwrap_or_default()),
+            buffer: [0; 4096],
+            paused: false,
+        };
+        watcher.add_watches(index, 0);
+        Ok(watcher)
+    }
+
+    /// handle new inotify events and all files that settled meanwhile unless paused, never blocks
+    /// returns false when there were no new events
+    pub fn poll(&mut self, index: &mut Index, actions: &mut Actions) -> std::io::Result<bool> {
+        let mut new_paths = Vec::new();
+        match self.inotify.read_events(&mut self.buffer) {
+            Ok(events) => {
+                for event in events {
+                    if event.mask.contains(EventMask::Q_OVERFLOW) {
+                        eprintln!("inotify queue overflow, some new files are not deduplicated");
+                        continue;
+                    }
+                    if let (Some(&dir_index), Some(name)) =
+                        (self.watched.get(&event.wd), event.name)
+                    {
+                        new_paths.push((index.all_dirs[dir_index].join(name), event.mask));
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        let had_events = !new_paths.is_empty();
+        for (path, mask) in new_paths {
+            if mask.contains(EventMask::ISDIR) {
+                self.new_dir(index, &path);
+            } else {
+                let open = mask.intersects(EventMask::CREATE | EventMask::MODIFY);
+                self.queue(path, open);
+            }
+        }
+        if self.paused {
+            return Ok(had_events);
+        }
+        let settled: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| !pending.open && pending.changed.elapsed() >= self.settle)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            if let Some(pending) = self.pending.remove(&path) {
+                self.new_file(index, actions, &path, pending.size);
+            }
+        }
+        Ok(had_events)
+    }
+
+    /// start the settle time of path again, the size is taken when it is queued first
+    /// open tells whether the event was a write rather than a close or a rename
+    fn queue(&mut self, path: PathBuf, open: bool) {
+        let now = Instant::now();
+        self.pending
+            .entry(path)
+            .and_modify(|pending| {
+                pending.changed = now;
+                pending.open = open;
+            })
+            .or_insert_with_key(|path| Pending {
+                changed: now,
+                size: fs::symlink_metadata(path).ok().map(|m| m.len()),
+                open,
+            });
+    }
+
+    /// the file changed within the settle time or since it was queued, e.g. because a writer
+    /// paused longer than the settle time, so it may still be written to
+    fn still_changing(&self, metadata: &fs::Metadata, queued_size: Option<u64>) -> bool {
+        let now = SystemTime::now();
+        let recent = |age_by| {
+            filter::file_time(metadata, age_by)
+                .is_none_or(|time| now.duration_since(time).unwrap_or_default() < self.settle)
+        };
+        queued_size != Some(metadata.len()) || recent(AgeBy::Mtime) || recent(AgeBy::Ctime)
+    }
+
+    /// watch all directories from all_dirs[first..]
+    fn add_watches(&mut self, index: &Index, first: usize) {
+        // writes are watched as well, a file is only settled when it was closed after the last
+        // write and nobody wrote to it for a while
+        let mask =
+            WatchMask::CREATE | WatchMask::MODIFY | WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO;
+        let mut failed = 0;
+        for dir_index in first..index.all_dirs.len() {
+            match self.inotify.watches().add(&index.all_dirs[dir_index], mask) {
+                Ok(wd) => {
+                    self.watched.insert(wd, dir_index);
+                }
+                Err(_) => failed += 1,
+            }
+        }
+        if failed > 0 {
+            eprintln!("could not watch {failed} directories, check fs.inotify.max_user_watches");
+        }
+    }
+
+    /// ignore files of all directories from the scanned root down to dir
+    fn ignores_for(&self, dir: &Path) -> IgnoreStack {
+        let mut ignores = IgnoreStack::new(&self.args.ignore_file);
+        let mut roots = self.args.directories.iter().chain(&self.args.reference);
+        if let Some(root) = roots.find(|r| dir.starts_with(r)) {
+            let mut current = root.clone();
+            ignores.enter(&current);
+            for component in dir.strip_prefix(root).unwrap().components() {
+                current.push(component);
+                ignores.enter(&current);
+            }
+        }
+        ignores
+    }
+
+    /// scan a new directory like the initial scan did and queue all its files
+    fn new_dir(&mut self, index: &mut Index, path: &Path) {
+        let parent = path.parent().unwrap_or(path);
+        let mut ignores = self.ignores_for(parent);
+        if !self.filter.accepts_name(path, false)
+            || ignores.is_ignored(path, true)
+            || path.join(".keep_duplicates").exists()
+        {
+            return;
+        }
+        let first_dir = index.all_dirs.len();
+        let mut new_files = Vec::new();
+        self.filter.now = SystemTime::now();
+        find_files(
+            path,
+            &mut index.all_dirs,
+            &mut new_files,
+            &self.filter,
+            &mut ignores,
+            None,
+        );
+        for dir_index in first_dir..index.all_dirs.len() {
+            self.dir_indices
+                .insert(index.all_dirs[dir_index].clone(), dir_index);
+        }
+        self.add_watches(index, first_dir);
+        for file in new_files {
+            self.queue(index.all_dirs[file.dir_index].join(&file.name), false);
+        }
+    }
+
+    /// compare a settled file with all files of the same size and link it to a duplicate
+    fn new_file(
+        &mut self,
+        index: &mut Index,
+        actions: &mut Actions,
+        path: &Path,
+        queued_size: Option<u64>,
+    ) {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return,
+        };
+        // wait for another settle time with the current size
+        if self.still_changing(&metadata, queued_size) {
+            self.pending.insert(
+                path.to_path_buf(),
+                Pending {
+                    changed: Instant::now(),
+                    size: Some(metadata.len()),
+                    open: false,
+                },
+            );
+            return;
+        }
+        let (dir, name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir, name.to_owned()),
+            _ => return,
+        };
+        let dir_index = match self.dir_indices.get(dir) {
+            Some(&dir_index) => dir_index,
+            None => return,
+        };
+        self.filter.now = SystemTime::now();
+        if !self.filter.accepts_name(path, true)
+            || !self.filter.accepts_file(&metadata)
+            || self.ignores_for(dir).is_ignored(path, false)
+        {
+            return;
+        }
+        let size = metadata.len();
+        let mut id = metadata.ino() as FileId;
+        // content might have changed, forget old hash and entry
+        index.full_hash_cache.remove(&(id, size));
+        index.revalidate(size);
+        let group = index.size_group(size);
+        if let Some(i) = index.files[group.clone()]
+            .iter()
+            .position(|f| f.dir_index == dir_index && f.name == name)
+        {
+            index.files.remove(group.start + i);
+        }
+        let group = index.size_group(size);
+        // already linked to a known file or in a reference root, nothing to do
+        if self.roots.writable(dir) && !index.files[group.clone()].iter().any(|f| f.id == id) {
+            // one representative per set of linked files
+            let mut candidates: Vec<usize> = Vec::new();
+            for i in group.clone() {
+                let other_dir = &index.all_dirs[index.files[i].dir_index];
+                if self.roots.references && !self.roots.preferred(other_dir) {
+                    continue;
+                }
+                if !candidates
+                    .iter()
+                    .any(|&c| index.files[c].id == index.files[i].id)
+                {
+                    candidates.push(i);
+                }
+            }
+            let duplicate = if candidates.len() == 1 {
+                let other = &index.files[candidates[0]];
+                file_cmp(&index.all_dirs[other.dir_index], &other.name, dir, &name)
+                    .then_some(candidates[0])
+            } else if candidates.len() > 1 {
+                // same strategy as the full scan: more than two candidates are compared by hash,
+                // equal hashes are confirmed byte by byte unless they are trusted
+                match full_hash(dir, &name) {
+                    Ok(hash) => {
+                        index.full_hash_cache.insert((id, size), hash);
+                        candidates.into_iter().find(|&c| {
+                            if index.cached_hash(c) != Some(hash) {
+                                return false;
+                            }
+                            let other = &index.files[c];
+                            self.args.trust_hash
+                                || file_cmp(
+                                    &index.all_dirs[other.dir_index],
+                                    &other.name,
+                                    dir,
+                                    &name,
+                                )
+                        })
+                    }
+                    Err(_) => None,
+                }
+            } else {
+                None
+            };
+            if let Some(other) = duplicate {
+                let other = &index.files[other];
+                actions.duplicate(
+                    &index.all_dirs[other.dir_index],
+                    &other.name,
+                    dir,
+                    &name,
+                    size,
+                    false,
+                );
+                if actions.link {
+                    id = other.id;
+                }
+            }
+        }
+        index.files.insert(
+            group.end,
+            FileInfo {
+                dir_index,
+                id,
+                size,
+                name,
+            },
+        );
+    }
+}
+
+impl Index {
+    /// range of files with the given size, files are sorted by size
+    fn size_group(&self, size: u64) -> std::ops::Range<usize> {
+        self.files.partition_point(|f| f.size < size)
+            ..self.files.partition_point(|f| f.size <= size)
+    }
+
+    /// drop files that vanished or changed size since they were scanned, update ids of relinked ones
+    fn revalidate(&mut self, size: u64) {
+        let mut i = self.size_group(size).start;
+        while i < self.files.len() && self.files[i].size == size {
+            let file = &mut self.files[i];
+            match fs::symlink_metadata(self.all_dirs[file.dir_index].join(&file.name)) {
+                Ok(metadata) if metadata.is_file() && metadata.len() == size => {
+                    file.id = metadata.ino() as FileId;
+                    i += 1;
+                }
+                _ => {
+                    self.files.remove(i);
+                }
+            }
+        }
+    }
+
+    fn cached_hash(&mut self, index: usize) -> Option<FullHash> {
+        let file = &self.files[index];
+        let key = (file.id, file.size);
+        if let Some(hash) = self.full_hash_cache.get(&key) {
+            return Some(*hash);
+        }
+        let hash = full_hash(&self.all_dirs[file.dir_index], &file.name).ok()?;
+        self.full_hash_cache.insert(key, hash);
+        Some(hash)
+    }
+}